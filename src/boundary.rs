@@ -1,44 +1,45 @@
-use rocket::http::Status;
-use rocket::response::status;
 use rocket::State;
 use rocket_contrib::JSON;
 use proto;
+use error::Error;
+use operation::{self, Operation};
+use journal::Author;
+use revision::{IfMatch, Tagged};
+
+#[get("/<problem>/Boundary")]
+fn get(state: State<super::State>, problem: &str) -> Result<Tagged<JSON<proto::Boundary>>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        problem.boundary.clone()
+            .map(|boundary| Tagged(JSON(boundary), problem.version()))
+            .ok_or_else(|| Error::unset("boundary"))
+    })
+}
+
+#[delete("/<problem>/Boundary")]
+fn delete(state: State<super::State>,
+          if_match: IfMatch,
+          author: Author,
+          problem: &str) -> Result<Tagged<()>, Error> {
+    operation::apply(&state, problem, &if_match, &author, Operation::DeleteBoundary)
+}
 
 #[post("/<problem>/Boundary", data = "<boundary>")]
 fn post(state: State<super::State>,
+        if_match: IfMatch,
+        author: Author,
         problem: &str,
-        boundary: JSON<proto::Boundary>) -> status::Custom<()> {
-    // Attempt to access the problem.
-    if let Some(problem) = state.lock().unwrap().get_mut(problem) {
-        if problem.boundary.is_some() {
-            status::Custom(Status::Conflict, ())
-        } else {
-            problem.boundary = Some(boundary.0);
-            status::Custom(Status::Ok, ())
-        }
-    } else {
-        status::Custom(Status::NotFound, ())
-    }
+        boundary: JSON<proto::Boundary>) -> Result<Tagged<()>, Error> {
+    operation::apply(&state, problem, &if_match, &author, Operation::AddBoundary(boundary.0))
 }
 
 #[put("/<problem>/Boundary", data = "<boundary>")]
 fn put(state: State<super::State>,
+       if_match: IfMatch,
+       author: Author,
        problem: &str,
-       boundary: JSON<proto::Boundary>) -> status::Custom<()> {
-    // Attempt to access the problem.
-    if let Some(problem) = state.lock().unwrap().get_mut(problem) {
-        // The boundary already existed, so put succeeds.
-        if problem.boundary.is_some() {
-            problem.boundary = Some(boundary.0);
-            status::Custom(Status::Ok, ())
-        // No boundary existed.
-        } else {
-            status::Custom(Status::Conflict, ())
-        }
-    // Failed to access the problem.
-    } else {
-        status::Custom(Status::NotFound, ())
-    }
+       boundary: JSON<proto::Boundary>) -> Result<Tagged<()>, Error> {
+    operation::apply(&state, problem, &if_match, &author, Operation::ReplaceBoundary(boundary.0))
 }
 
 #[cfg(test)]
@@ -46,9 +47,19 @@ mod test {
     extern crate serde;
     extern crate serde_json;
     use rocket::testing::MockRequest;
+    use rocket::Response;
     use rocket::http::{Status, Method, ContentType};
     use proto;
 
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
     #[test]
     fn test() {
         // Make the mock server.
@@ -113,5 +124,25 @@ mod test {
             }).unwrap());
         let response = request.dispatch_with(&rocket);
         assert_eq!(response.status(), Status::Ok);
+
+        // Get the boundary back.
+        let mut request = MockRequest::new(Method::Get, "/test/Boundary");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let body = body_deser::<proto::Boundary>(&mut response).unwrap();
+        assert_eq!(body.width, 10.0);
+
+        // Clear the boundary.
+        let mut request = MockRequest::new(Method::Delete, "/test/Boundary");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Now that it is cleared, getting or clearing it again fails.
+        let mut request = MockRequest::new(Method::Get, "/test/Boundary");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let mut request = MockRequest::new(Method::Delete, "/test/Boundary");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
     }
-}
\ No newline at end of file
+}
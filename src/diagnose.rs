@@ -0,0 +1,185 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use proto;
+use grid::{Cell, Grid, NEIGHBOURS};
+use planner::Expansions;
+
+/// An explanation of why no path exists between the robot and the goal.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Diagnosis {
+    /// One of `start_blocked`, `goal_blocked` or `disconnected`.
+    pub reason: &'static str,
+    /// The obstacles covering the blocked start or goal, or separating them from each other.
+    pub obstacles: Vec<String>,
+}
+
+impl Diagnosis {
+    fn new(reason: &'static str, obstacles: Vec<String>) -> Diagnosis {
+        Diagnosis {
+            reason: reason,
+            obstacles: obstacles,
+        }
+    }
+}
+
+/// Works out why a planner found no path from `start` to `goal` on `grid`.
+///
+/// Returns `None` if `expansions` ran out before the answer was known.
+pub fn diagnose(grid: &Grid,
+                obstacles: &HashMap<String, proto::Obstacle>,
+                start: Cell,
+                goal: Cell,
+                expansions: &Expansions) -> Option<Diagnosis> {
+    if !grid.is_free(start) {
+        let cells = Some(start).into_iter().collect();
+        return covering(grid, obstacles, &cells, expansions)
+            .map(|ids| Diagnosis::new("start_blocked", ids));
+    }
+    if !grid.is_free(goal) {
+        let cells = Some(goal).into_iter().collect();
+        return covering(grid, obstacles, &cells, expansions)
+            .map(|ids| Diagnosis::new("goal_blocked", ids));
+    }
+
+    // The obstacles separating the start from the goal border both the region around the
+    // start and the one around the goal. Obstacles that only border one of them, such as one
+    // standing in the middle of it, aren't in the way.
+    let around_start = match border(grid, start, expansions)
+        .and_then(|cells| covering(grid, obstacles, &cells, expansions)) {
+        Some(ids) => ids,
+        None => return None,
+    };
+    let around_goal = match border(grid, goal, expansions)
+        .and_then(|cells| covering(grid, obstacles, &cells, expansions)) {
+        Some(ids) => ids,
+        None => return None,
+    };
+    let between: Vec<String> = around_start.iter()
+        .filter(|&id| around_goal.contains(id))
+        .cloned()
+        .collect();
+    // Layers of obstacles can separate the regions without any one of them touching both.
+    let obstacles = if between.is_empty() { around_start } else { between };
+    Some(Diagnosis::new("disconnected", obstacles))
+}
+
+/// The occupied cells bordering the region that can be reached from `start`, or `None` if
+/// `expansions` ran out while finding it.
+fn border(grid: &Grid, start: Cell, expansions: &Expansions) -> Option<HashSet<Cell>> {
+    let reachable = match grid.reachable(start, expansions) {
+        Some(reachable) => reachable,
+        None => return None,
+    };
+    let mut cells = HashSet::new();
+    for ((x, y), &reached) in reachable.indexed_iter() {
+        if !reached {
+            continue;
+        }
+        for &add in &NEIGHBOURS {
+            if let Some(neighbour) = grid.offset([x, y], add) {
+                if !grid.is_free(neighbour) {
+                    cells.insert(neighbour);
+                }
+            }
+        }
+    }
+    Some(cells)
+}
+
+/// The sorted IDs of the obstacles whose inflated footprint covers any of the cells, or
+/// `None` if `expansions` ran out.
+///
+/// Each obstacle's footprint is worked out once, and then checked cell by cell against
+/// whichever of it and `cells` is smaller, every cell looked at counting as an expansion.
+fn covering(grid: &Grid,
+            obstacles: &HashMap<String, proto::Obstacle>,
+            cells: &HashSet<Cell>,
+            expansions: &Expansions) -> Option<Vec<String>> {
+    let mut ids = BTreeSet::new();
+    for (id, obstacle) in obstacles {
+        let (low, high) = match grid.footprint(obstacle) {
+            Some(footprint) => footprint,
+            None => continue,
+        };
+        let area = (high[0] - low[0] + 1) * (high[1] - low[1] + 1);
+        let mut covered = false;
+        if area <= cells.len() {
+            'footprint: for x in low[0]..high[0] + 1 {
+                for y in low[1]..high[1] + 1 {
+                    if !expansions.count() {
+                        return None;
+                    }
+                    if cells.contains(&[x, y]) {
+                        covered = true;
+                        break 'footprint;
+                    }
+                }
+            }
+        } else {
+            for cell in cells {
+                if !expansions.count() {
+                    return None;
+                }
+                if cell[0] >= low[0] && cell[0] <= high[0] &&
+                   cell[1] >= low[1] && cell[1] <= high[1] {
+                    covered = true;
+                    break;
+                }
+            }
+        }
+        if covered {
+            ids.insert(id.clone());
+        }
+    }
+    Some(ids.into_iter().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use proto;
+    use grid::Grid;
+    use planner::Expansions;
+
+    fn obstacle(x: f64, y: f64, width: f64, length: f64) -> proto::Obstacle {
+        proto::Obstacle{
+            width: width,
+            length: length,
+            point: proto::Point{ x: x, y: y },
+        }
+    }
+
+    #[test]
+    fn test() {
+        let boundary = proto::Boundary{
+            width: 10.0,
+            length: 10.0,
+            point: proto::Point{ x: 0.0, y: 0.0 },
+        };
+        let robot = proto::Robot{
+            point: proto::Point{ x: 1.0, y: 5.0 },
+            radius: 0.4,
+        };
+
+        // Two walls split the map in half, and a box on either side is irrelevant.
+        let mut obstacles = HashMap::new();
+        obstacles.insert(String::from("lower"), obstacle(4.5, 0.0, 1.0, 5.0));
+        obstacles.insert(String::from("upper"), obstacle(4.5, 5.0, 1.0, 5.0));
+        obstacles.insert(String::from("box"), obstacle(8.0, 8.0, 1.0, 1.0));
+        obstacles.insert(String::from("crate"), obstacle(2.0, 2.0, 1.0, 1.0));
+        let grid = Grid::new(&boundary, &robot, 0.1, obstacles.values());
+
+        let start = grid.to_coords([1.0, 5.0]).unwrap();
+        let goal = grid.to_coords([9.0, 5.0]).unwrap();
+        let diagnosis = super::diagnose(&grid, &obstacles, start, goal, &Expansions::default())
+            .unwrap();
+        assert_eq!(diagnosis.reason, "disconnected");
+        assert_eq!(diagnosis.obstacles, vec![String::from("lower"), String::from("upper")]);
+        assert!(!diagnosis.obstacles.contains(&String::from("box")));
+
+        let goal = grid.to_coords([8.5, 8.5]).unwrap();
+        let diagnosis = super::diagnose(&grid, &obstacles, start, goal, &Expansions::default())
+            .unwrap();
+        assert_eq!(diagnosis.reason, "goal_blocked");
+        assert_eq!(diagnosis.obstacles, vec![String::from("box")]);
+    }
+}
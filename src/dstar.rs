@@ -0,0 +1,382 @@
+use ndarray::Array2;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::mem;
+use std::sync::Mutex;
+use std::u64;
+use proto;
+
+use itertools::Itertools;
+use grid::{self, Cell, Grid, Layout, NEIGHBOURS};
+use planner::{Expansions, Planner};
+
+/// The cost of an edge into or out of an occupied cell, and the distance to unreachable cells.
+const INFINITY: u64 = u64::MAX;
+
+/// Obstacle changes remembered between plans, beyond which every cell is checked instead.
+const MAX_CHANGES: usize = 256;
+
+/// The search state D* Lite keeps between path requests on the same problem.
+///
+/// It searches backwards from the goal, so when the robot moves or cells change only the
+/// affected part of the previous search has to be repaired. The cells to repair are those
+/// covered by the obstacles passed to `obstacle_changed`.
+///
+/// A copy starts without a search, since it isn't told about changes to the original.
+#[derive(Default)]
+pub struct DStarLite {
+    search: Mutex<Option<Search>>,
+}
+
+impl Clone for DStarLite {
+    fn clone(&self) -> DStarLite {
+        DStarLite::default()
+    }
+}
+
+impl DStarLite {
+    /// Notes that an obstacle was added or removed, so the next plan repairs what it covers.
+    pub fn obstacle_changed(&mut self, obstacle: &proto::Obstacle) {
+        let search = self.search.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some(ref mut search) = *search {
+            search.obstacle_changed(obstacle);
+        }
+    }
+
+    /// Takes the search out, leaving none behind.
+    pub fn take(&mut self) -> DStarLite {
+        mem::replace(self, DStarLite::default())
+    }
+}
+
+impl Planner for DStarLite {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        if !grid.is_free(start) || !grid.is_free(goal) {
+            return None;
+        }
+        // A search that panicked part way through can't be trusted, so start over.
+        let mut search = self.search.lock().unwrap_or_else(|e| {
+            let mut search = e.into_inner();
+            *search = None;
+            search
+        });
+        let reusable = search.as_ref()
+            .map_or(false, |s| s.goal == goal && s.layout == grid.layout());
+        if !reusable {
+            *search = Some(Search::new(grid, start, goal));
+        }
+        search.as_mut().unwrap().plan(grid, start, expansions)
+    }
+}
+
+/// The priority of a cell in the open list, compared lexicographically.
+type Key = (u64, u64);
+
+struct Search {
+    goal: Cell,
+    /// Where the robot was at the last plan, for correcting keys as it moves.
+    last: Cell,
+    /// How far the robot has moved in total, added to every key instead of reordering the heap.
+    km: u64,
+    g: HashMap<Cell, u64>,
+    rhs: HashMap<Cell, u64>,
+    /// The current key of every cell in the open list, which makes older heap entries stale.
+    keys: HashMap<Cell, Key>,
+    open: BinaryHeap<Entry>,
+    /// The grid the search was made for.
+    layout: Layout,
+    /// The free cells as of the last plan.
+    free: Array2<bool>,
+    /// The obstacles added or removed since the last plan, whose cells may have changed, or
+    /// `None` if there were too many to be worth keeping track of.
+    changes: Option<Vec<proto::Obstacle>>,
+}
+
+impl Search {
+    fn new(grid: &Grid, start: Cell, goal: Cell) -> Search {
+        let mut free = Array2::from_elem((grid.width(), grid.height()), false);
+        for ((x, y), cell) in free.indexed_iter_mut() {
+            *cell = grid.is_free([x, y]);
+        }
+        let mut search = Search {
+            goal: goal,
+            last: start,
+            km: 0,
+            g: HashMap::new(),
+            rhs: HashMap::new(),
+            keys: HashMap::new(),
+            open: BinaryHeap::new(),
+            layout: grid.layout(),
+            free: free,
+            changes: Some(Vec::new()),
+        };
+        search.rhs.insert(goal, 0);
+        let key = search.key(goal);
+        search.push(goal, key);
+        search
+    }
+
+    fn obstacle_changed(&mut self, obstacle: &proto::Obstacle) {
+        let too_many = match self.changes {
+            Some(ref mut changes) => {
+                changes.push(obstacle.clone());
+                changes.len() > MAX_CHANGES
+            }
+            None => false,
+        };
+        if too_many {
+            self.changes = None;
+        }
+    }
+
+    /// Compares a cell against the grid, adding it to `changed` if it was freed or occupied.
+    fn refresh(&mut self, grid: &Grid, cell: Cell, changed: &mut Vec<Cell>) {
+        let now = grid.is_free(cell);
+        if self.free[cell] != now {
+            self.free[cell] = now;
+            changed.push(cell);
+        }
+    }
+
+    fn plan(&mut self, grid: &Grid, start: Cell, expansions: &Expansions) -> Option<Vec<Cell>> {
+        self.km = self.km.saturating_add(grid::octile_distance(self.last, start));
+        self.last = start;
+
+        // Repair the cells whose occupancy changed, along with everything next to them.
+        let mut changed = Vec::new();
+        match mem::replace(&mut self.changes, Some(Vec::new())) {
+            Some(obstacles) => {
+                for (low, high) in obstacles.iter().filter_map(|o| grid.footprint(o)) {
+                    for (x, y) in (low[0]..high[0] + 1).cartesian_product(low[1]..high[1] + 1) {
+                        self.refresh(grid, [x, y], &mut changed);
+                    }
+                }
+            }
+            None => {
+                for (x, y) in (0..grid.width()).cartesian_product(0..grid.height()) {
+                    self.refresh(grid, [x, y], &mut changed);
+                }
+            }
+        }
+        for cell in changed {
+            self.update(grid, cell);
+            for &add in &NEIGHBOURS {
+                if let Some(neighbour) = grid.offset(cell, add) {
+                    self.update(grid, neighbour);
+                }
+            }
+        }
+
+        if !self.compute(grid, start, expansions) || self.g(start) == INFINITY {
+            return None;
+        }
+
+        // Walk downhill from the start, which can't take more steps than there are cells.
+        let mut path = vec![start];
+        let mut current = start;
+        while current != self.goal {
+            if path.len() > grid.width() * grid.height() {
+                return None;
+            }
+            let next = NEIGHBOURS.iter()
+                .filter_map(|&add| grid.offset(current, add).map(|n| (n, add)))
+                .map(|(n, add)| (n, self.cost(current, n, add).saturating_add(self.g(n))))
+                .min_by_key(|&(_, cost)| cost);
+            match next {
+                Some((next, cost)) if cost != INFINITY => {
+                    path.push(next);
+                    current = next;
+                }
+                _ => return None,
+            }
+        }
+        Some(path)
+    }
+
+    fn g(&self, cell: Cell) -> u64 {
+        self.g.get(&cell).cloned().unwrap_or(INFINITY)
+    }
+
+    fn rhs(&self, cell: Cell) -> u64 {
+        self.rhs.get(&cell).cloned().unwrap_or(INFINITY)
+    }
+
+    fn key(&self, cell: Cell) -> Key {
+        let best = ::std::cmp::min(self.g(cell), self.rhs(cell));
+        (best.saturating_add(grid::octile_distance(self.last, cell)).saturating_add(self.km),
+         best)
+    }
+
+    fn cost(&self, from: Cell, to: Cell, add: [isize; 2]) -> u64 {
+        if self.free[from] && self.free[to] {
+            grid::step_cost(add)
+        } else {
+            INFINITY
+        }
+    }
+
+    fn push(&mut self, cell: Cell, key: Key) {
+        self.keys.insert(cell, key);
+        self.open.push(Entry { key: key, cell: cell });
+    }
+
+    /// Recomputes the best distance to the goal through the neighbours of a cell.
+    fn update(&mut self, grid: &Grid, cell: Cell) {
+        if cell != self.goal {
+            let rhs = NEIGHBOURS.iter()
+                .filter_map(|&add| grid.offset(cell, add).map(|n| (n, add)))
+                .map(|(n, add)| self.cost(cell, n, add).saturating_add(self.g(n)))
+                .min()
+                .unwrap_or(INFINITY);
+            self.rhs.insert(cell, rhs);
+        }
+        if self.g(cell) != self.rhs(cell) {
+            let key = self.key(cell);
+            self.push(cell, key);
+        } else {
+            self.keys.remove(&cell);
+        }
+    }
+
+    /// Expands inconsistent cells until the distance from the start is settled.
+    ///
+    /// Returns `false` if the budget ran out first, leaving the search to carry on next time.
+    fn compute(&mut self, grid: &Grid, start: Cell, expansions: &Expansions) -> bool {
+        loop {
+            // Drop entries for cells that were requeued or became consistent.
+            let top = match self.open.peek() {
+                Some(top) => (top.key, top.cell),
+                None => return true,
+            };
+            if self.keys.get(&top.1) != Some(&top.0) {
+                self.open.pop();
+                continue;
+            }
+            let (old_key, cell) = top;
+            if old_key >= self.key(start) && self.rhs(start) == self.g(start) {
+                return true;
+            }
+            if !expansions.count() {
+                return false;
+            }
+            self.open.pop();
+
+            let new_key = self.key(cell);
+            if old_key < new_key {
+                self.push(cell, new_key);
+            } else if self.g(cell) > self.rhs(cell) {
+                let rhs = self.rhs(cell);
+                self.g.insert(cell, rhs);
+                self.keys.remove(&cell);
+                self.update_neighbours(grid, cell);
+            } else {
+                self.g.insert(cell, INFINITY);
+                self.update(grid, cell);
+                self.update_neighbours(grid, cell);
+            }
+        }
+    }
+
+    fn update_neighbours(&mut self, grid: &Grid, cell: Cell) {
+        for &add in &NEIGHBOURS {
+            if let Some(neighbour) = grid.offset(cell, add) {
+                self.update(grid, neighbour);
+            }
+        }
+    }
+}
+
+/// An entry in the open list, ordered so the lowest key pops first.
+#[derive(PartialEq, Eq)]
+struct Entry {
+    key: Key,
+    cell: Cell,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        other.key.cmp(&self.key)
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proto;
+    use grid::{self, Grid};
+    use planner::{AStar, Expansions, Planner};
+    use super::DStarLite;
+
+    fn obstacle(x: f64, y: f64, width: f64, length: f64) -> proto::Obstacle {
+        proto::Obstacle{
+            width: width,
+            length: length,
+            point: proto::Point{ x: x, y: y },
+        }
+    }
+
+    fn cost(path: &[[usize; 2]]) -> u64 {
+        path.windows(2).map(|w| grid::octile_distance(w[0], w[1])).sum()
+    }
+
+    #[test]
+    fn test() {
+        let boundary = proto::Boundary{
+            width: 10.0,
+            length: 10.0,
+            point: proto::Point{ x: 0.0, y: 0.0 },
+        };
+        let robot = proto::Robot{
+            point: proto::Point{ x: 1.0, y: 5.0 },
+            radius: 0.4,
+        };
+        let mut obstacles = vec![obstacle(4.5, 2.0, 1.0, 6.0)];
+        let mut grid = Grid::new(&boundary, &robot, 0.25, &obstacles);
+        let goal = grid.to_coords([9.0, 5.0]).unwrap();
+        let mut dstar = DStarLite::default();
+
+        // The first search finds a path as short as A* does.
+        let start = grid.to_coords([1.0, 5.0]).unwrap();
+        let first = Expansions::default();
+        let path = dstar.plan(&grid, start, goal, &first).unwrap();
+        let optimal = AStar.plan(&grid, start, goal, &Expansions::default()).unwrap();
+        assert_eq!(cost(&path), cost(&optimal));
+
+        // Moving the robot along the path reuses almost all of the search.
+        let start = path[4];
+        let moved = Expansions::default();
+        let path = dstar.plan(&grid, start, goal, &moved).unwrap();
+        assert_eq!(cost(&path), cost(&AStar.plan(&grid, start, goal, &Expansions::default())
+            .unwrap()));
+        assert!(moved.total() < first.total());
+
+        // A new obstacle in the way is routed around.
+        obstacles.push(obstacle(6.0, 0.0, 1.0, 7.0));
+        grid.add_obstacle(&obstacles[1]);
+        dstar.obstacle_changed(&obstacles[1]);
+        let path = dstar.plan(&grid, start, goal, &Expansions::default()).unwrap();
+        assert_eq!(cost(&path), cost(&AStar.plan(&grid, start, goal, &Expansions::default())
+            .unwrap()));
+        assert!(path.iter().all(|&cell| grid.is_free(cell)));
+
+        // Closing the gap completely leaves no path, and opening it again brings it back.
+        obstacles.push(obstacle(6.0, 7.0, 1.0, 3.0));
+        grid.add_obstacle(&obstacles[2]);
+        dstar.obstacle_changed(&obstacles[2]);
+        assert!(dstar.plan(&grid, start, goal, &Expansions::default()).is_none());
+        grid.remove_obstacle(&obstacles[2]);
+        dstar.obstacle_changed(&obstacles[2]);
+        assert!(dstar.plan(&grid, start, goal, &Expansions::default()).is_some());
+
+        // A copy starts its own search from scratch.
+        let copied = Expansions::default();
+        dstar.clone().plan(&grid, start, goal, &copied).unwrap();
+        assert!(copied.total() > moved.total());
+    }
+}
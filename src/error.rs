@@ -0,0 +1,171 @@
+use rocket::http::Status;
+use rocket::response::{self, Responder, status};
+use rocket::Request;
+use rocket_contrib::JSON;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+/// An error returned by a route, sent to the client as a JSON body.
+#[derive(Clone, Serialize)]
+pub struct Error {
+    #[serde(skip_serializing)]
+    pub status: Status,
+    /// Machine-readable identifier of the kind of error, such as `not_found`.
+    pub code: &'static str,
+    /// Human-readable explanation of what went wrong.
+    pub message: String,
+    /// The path parameter, query parameter or body field that caused the error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Extra information specific to the kind of error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl Error {
+    pub fn new<S: Into<String>>(status: Status, code: &'static str, message: S) -> Error {
+        Error {
+            status: status,
+            code: code,
+            message: message.into(),
+            field: None,
+            details: None,
+        }
+    }
+
+    /// Attributes the error to a field.
+    pub fn with_field<S: Into<String>>(mut self, field: S) -> Error {
+        self.field = Some(field.into());
+        self
+    }
+
+    /// Attaches extra information to the error.
+    pub fn with_details<T: Serialize>(mut self, details: T) -> Error {
+        self.details = serde_json::to_value(details).ok();
+        self
+    }
+
+    pub fn not_found<S: Into<String>>(field: &str, message: S) -> Error {
+        Error::new(Status::NotFound, "not_found", message).with_field(field)
+    }
+
+    pub fn problem_not_found(problem: &str) -> Error {
+        Error::not_found("problem", format!("problem \"{}\" does not exist", problem))
+    }
+
+    pub fn already_exists<S: Into<String>>(field: &str, message: S) -> Error {
+        Error::new(Status::Conflict, "already_exists", message).with_field(field)
+    }
+
+    /// A POST to an entity of a problem that is already set.
+    pub fn already_set(field: &str) -> Error {
+        Error::new(Status::Conflict,
+                   "already_set",
+                   format!("the {} is already set, use PUT to replace it", field))
+            .with_field(field)
+    }
+
+    /// A PUT to an entity of a problem that was never set.
+    pub fn not_set(field: &str) -> Error {
+        Error::new(Status::Conflict,
+                   "not_set",
+                   format!("the {} is not set, use POST to create it", field))
+            .with_field(field)
+    }
+
+    /// A GET or DELETE of an entity of a problem that isn't set.
+    pub fn unset(field: &str) -> Error {
+        Error::not_found(field, format!("the {} is not set", field))
+    }
+
+    /// An entity of a problem that must be set before the request can be handled.
+    pub fn missing(field: &str) -> Error {
+        Error::new(Status::BadRequest, "missing", format!("the {} is not set", field))
+            .with_field(field)
+    }
+
+    /// A parameter that couldn't be used.
+    pub fn invalid<S: Into<String>>(field: &str, message: S) -> Error {
+        Error::new(Status::BadRequest, "invalid", message).with_field(field)
+    }
+
+    /// A query parameter the route doesn't know about.
+    pub fn unknown_parameter(field: &str) -> Error {
+        Error::invalid(field, format!("unknown parameter \"{}\"", field))
+    }
+}
+
+impl<'r> Responder<'r> for Error {
+    fn respond(self) -> response::Result<'r> {
+        let status = self.status;
+        status::Custom(status, JSON(self)).respond()
+    }
+}
+
+#[error(400)]
+fn bad_request(_: &Request) -> Error {
+    Error::new(Status::BadRequest, "bad_request", "the request could not be understood")
+}
+
+#[error(404)]
+fn not_found(_: &Request) -> Error {
+    Error::new(Status::NotFound, "not_found", "no such resource")
+}
+
+#[error(422)]
+fn unprocessable_entity(_: &Request) -> Error {
+    Error::new(Status::UnprocessableEntity,
+               "unprocessable_entity",
+               "the request body could not be parsed")
+}
+
+#[error(500)]
+fn internal_server_error(_: &Request) -> Error {
+    Error::new(Status::InternalServerError, "internal", "the server failed to handle the request")
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType};
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Remove a problem that doesn't exist.
+        let mut request = MockRequest::new(Method::Delete, "/test");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["field"], "problem");
+
+        // Add "test" to the problems.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Post a robot that isn't JSON, which is caught before reaching the route.
+        let mut request = MockRequest::new(Method::Post, "/test/Robot")
+            .header(ContentType::JSON)
+            .body("not a robot");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "bad_request");
+    }
+}
@@ -0,0 +1,331 @@
+use ndarray::Array2;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use proto;
+
+use itertools::Itertools;
+use planner::Expansions;
+
+/// A cell of the grid addressed as `[x, y]`.
+pub type Cell = [usize; 2];
+
+/// Offsets to the 8-connected neighbours of a cell.
+pub const NEIGHBOURS: [[isize; 2]; 8] =
+    [[-1, -1], [-1, 0], [-1, 1], [0, -1], [0, 1], [1, -1], [1, 0], [1, 1]];
+
+/// Cost of a straight step between grid cells, in fixed point so that costs stay `Ord`.
+pub const STRAIGHT_COST: u64 = 10_000;
+/// Cost of a diagonal step (√2 rounded down so the octile heuristic remains admissible).
+pub const DIAGONAL_COST: u64 = 14_142;
+
+/// Where a grid lies, how fine it is and how far obstacles are inflated, which together decide
+/// what each of its cells stands for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    origin: [f64; 2],
+    extent: [f64; 2],
+    granularity: f64,
+    inflation: f64,
+}
+
+/// Occupancy grid of a problem where every obstacle is inflated by the robot radius.
+///
+/// A cell is occupied when the center of the robot cannot be placed there. Each cell counts
+/// the obstacles covering it, so obstacles can be added and removed without a full rebuild.
+#[derive(Clone)]
+pub struct Grid {
+    origin: [f64; 2],
+    extent: [f64; 2],
+    granularity: f64,
+    inflation: f64,
+    width: usize,
+    height: usize,
+    occupied: Array2<u32>,
+}
+
+impl Grid {
+    /// Rasterizes the obstacles into a grid with cells `granularity` wide.
+    ///
+    /// Check `cell_count` first, since the grid is allocated up front.
+    pub fn new<'a, I>(boundary: &proto::Boundary,
+                      robot: &proto::Robot,
+                      granularity: f64,
+                      obstacles: I) -> Grid
+        where I: IntoIterator<Item = &'a proto::Obstacle>
+    {
+        let (width, height) = dimensions(boundary, granularity);
+        let mut grid = Grid {
+            origin: [boundary.point.x, boundary.point.y],
+            extent: [boundary.width, boundary.length],
+            granularity: granularity,
+            inflation: robot.radius,
+            width: width,
+            height: height,
+            occupied: Array2::zeros((width, height)),
+        };
+
+        for obstacle in obstacles {
+            grid.add_obstacle(obstacle);
+        }
+
+        grid
+    }
+
+    /// Whether the grid was built for this boundary, robot size and granularity.
+    pub fn fits(&self, boundary: &proto::Boundary, robot: &proto::Robot, granularity: f64) -> bool {
+        self.origin == [boundary.point.x, boundary.point.y] &&
+            self.extent == [boundary.width, boundary.length] &&
+            self.inflation == robot.radius && self.granularity == granularity
+    }
+
+    /// Marks the cells covered by an obstacle as occupied.
+    pub fn add_obstacle(&mut self, obstacle: &proto::Obstacle) {
+        if let Some((start, end)) = self.footprint(obstacle) {
+            for (x, y) in (start[0]..end[0] + 1).cartesian_product((start[1]..end[1] + 1)) {
+                self.occupied[[x, y]] += 1;
+            }
+        }
+    }
+
+    /// Undoes `add_obstacle`, leaving cells that other obstacles cover occupied.
+    pub fn remove_obstacle(&mut self, obstacle: &proto::Obstacle) {
+        if let Some((start, end)) = self.footprint(obstacle) {
+            for (x, y) in (start[0]..end[0] + 1).cartesian_product((start[1]..end[1] + 1)) {
+                self.occupied[[x, y]] = self.occupied[[x, y]].saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout {
+            origin: self.origin,
+            extent: self.extent,
+            granularity: self.granularity,
+            inflation: self.inflation,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn granularity(&self) -> f64 {
+        self.granularity
+    }
+
+    /// Converts a position into the cell containing it, or `None` if it is outside the boundary.
+    ///
+    /// Positions on the far edges of the boundary belong to the last row or column of cells.
+    pub fn to_coords(&self, pos: [f64; 2]) -> Option<Cell> {
+        let inside = |i: usize| {
+            pos[i] >= self.origin[i] && pos[i] <= self.origin[i] + self.extent[i]
+        };
+        if self.width == 0 || self.height == 0 || !inside(0) || !inside(1) {
+            return None;
+        }
+        let index = |i: usize, cells: usize| {
+            let index = ((pos[i] - self.origin[i]) / self.granularity + 0.5) as usize;
+            if index < cells { index } else { cells - 1 }
+        };
+        Some([index(0, self.width), index(1, self.height)])
+    }
+
+    /// The first and last cell covered by an obstacle once it is inflated by the robot radius.
+    ///
+    /// The footprint is clipped to the grid, and is `None` if the obstacle is entirely outside.
+    pub fn footprint(&self, obstacle: &proto::Obstacle) -> Option<(Cell, Cell)> {
+        let index = |pos: f64, i: usize| ((pos - self.origin[i]) / self.granularity + 0.5).floor();
+        let start = [index(obstacle.point.x - self.inflation, 0),
+            index(obstacle.point.y - self.inflation, 1)];
+        let end = [index(obstacle.point.x + obstacle.width + self.inflation, 0),
+            index(obstacle.point.y + obstacle.length + self.inflation, 1)];
+
+        // This also rules out NaN, which fails every comparison.
+        if !(start[0] <= end[0] && start[1] <= end[1]) || end[0] < 0.0 || end[1] < 0.0 ||
+            start[0] >= self.width as f64 || start[1] >= self.height as f64 {
+            return None;
+        }
+        let clip = |index: f64, cells: usize| {
+            if index < 0.0 {
+                0
+            } else if index >= cells as f64 {
+                cells - 1
+            } else {
+                index as usize
+            }
+        };
+        Some(([clip(start[0], self.width), clip(start[1], self.height)],
+            [clip(end[0], self.width), clip(end[1], self.height)]))
+    }
+
+    /// Converts a cell back into a position.
+    pub fn from_coords(&self, coord: Cell) -> [f64; 2] {
+        [coord[0] as f64 * self.granularity + self.origin[0],
+            coord[1] as f64 * self.granularity + self.origin[1]]
+    }
+
+    /// Offsets a cell, returning `None` if the result falls off the grid.
+    pub fn offset(&self, coord: Cell, add: [isize; 2]) -> Option<Cell> {
+        let new_coord = [coord[0] as isize + add[0], coord[1] as isize + add[1]];
+        if let (Ok(x), Ok(y)) = (usize::try_from(new_coord[0]), usize::try_from(new_coord[1])) {
+            if x < self.width && y < self.height {
+                Some([x, y])
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Whether the cell is on the grid and not occupied.
+    pub fn is_free(&self, coord: Cell) -> bool {
+        coord[0] < self.width && coord[1] < self.height && self.occupied[coord] == 0
+    }
+
+    /// Same as `is_free`, but for signed coordinates that may be off the grid.
+    pub fn is_free_signed(&self, coord: [isize; 2]) -> bool {
+        coord[0] >= 0 && coord[1] >= 0 && self.is_free([coord[0] as usize, coord[1] as usize])
+    }
+
+    /// Whether a robot can travel in a straight line between the centers of two cells.
+    ///
+    /// Every cell the segment passes through must be free. Where the segment passes exactly
+    /// through a cell corner it steps diagonally, just like `successors` allows.
+    pub fn line_of_sight(&self, a: Cell, b: Cell) -> bool {
+        let (mut x, mut y) = (a[0] as isize, a[1] as isize);
+        let dx = (b[0] as isize - x).abs();
+        let dy = (b[1] as isize - y).abs();
+        let sx = (b[0] as isize - x).signum();
+        let sy = (b[1] as isize - y).signum();
+
+        if !self.is_free_signed([x, y]) {
+            return false;
+        }
+        let (mut ix, mut iy) = (0, 0);
+        while ix < dx || iy < dy {
+            // Compare where the segment crosses the next vertical and horizontal cell edges.
+            let decision = (1 + 2 * ix) * dy - (1 + 2 * iy) * dx;
+            if decision == 0 {
+                x += sx;
+                y += sy;
+                ix += 1;
+                iy += 1;
+            } else if decision < 0 {
+                x += sx;
+                ix += 1;
+            } else {
+                y += sy;
+                iy += 1;
+            }
+            if !self.is_free_signed([x, y]) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Flood fills the free cells reachable from `start`, counting every cell it expands.
+    ///
+    /// Returns `None` if `expansions` ran out before the fill was done.
+    pub fn reachable(&self, start: Cell, expansions: &Expansions) -> Option<Array2<bool>> {
+        let mut reached = Array2::from_elem((self.width, self.height), false);
+        if !self.is_free(start) {
+            return Some(reached);
+        }
+        let mut queue = VecDeque::new();
+        reached[start] = true;
+        queue.push_back(start);
+        while let Some(coord) = queue.pop_front() {
+            if !expansions.count() {
+                return None;
+            }
+            for (neighbour, _) in self.successors(coord) {
+                if !reached[neighbour] {
+                    reached[neighbour] = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        Some(reached)
+    }
+
+    /// The reachable cell closest to a position, found by flood filling from `start`.
+    ///
+    /// Returns `None` if `start` itself is occupied, or if `expansions` ran out.
+    pub fn nearest_reachable(&self, start: Cell, pos: [f64; 2], expansions: &Expansions)
+            -> Option<Cell> {
+        let distance = |coord: Cell| {
+            let p = self.from_coords(coord);
+            (p[0] - pos[0]).powi(2) + (p[1] - pos[1]).powi(2)
+        };
+        let reachable = match self.reachable(start, expansions) {
+            Some(reachable) => reachable,
+            None => return None,
+        };
+        let mut nearest: Option<(Cell, f64)> = None;
+        for ((x, y), &reached) in reachable.indexed_iter() {
+            if reached {
+                let d = distance([x, y]);
+                if nearest.map_or(true, |(_, best)| d < best) {
+                    nearest = Some(([x, y], d));
+                }
+            }
+        }
+        nearest.map(|(coord, _)| coord)
+    }
+
+    /// The free 8-connected neighbours of a cell along with the cost of stepping to them.
+    pub fn successors(&self, coord: Cell) -> Vec<(Cell, u64)> {
+        NEIGHBOURS.iter()
+            .filter_map(|&add| self.offset(coord, add).map(|n| (n, step_cost(add))))
+            .filter(|&(n, _)| self.occupied[n] == 0)
+            .collect_vec()
+    }
+}
+
+/// The granularity used when neither the problem nor the request specify one.
+pub fn default_granularity(robot: &proto::Robot) -> f64 {
+    robot.radius / 4.0
+}
+
+/// The number of cells along the width and length of a boundary.
+fn dimensions(boundary: &proto::Boundary, granularity: f64) -> (usize, usize) {
+    ((boundary.width / granularity) as usize, (boundary.length / granularity) as usize)
+}
+
+/// How many cells a grid over the boundary would have, saturating instead of overflowing.
+pub fn cell_count(boundary: &proto::Boundary, granularity: f64) -> usize {
+    let (width, height) = dimensions(boundary, granularity);
+    width.saturating_mul(height)
+}
+
+/// The cost of moving by a single neighbour offset.
+pub fn step_cost(add: [isize; 2]) -> u64 {
+    if add[0] != 0 && add[1] != 0 {
+        DIAGONAL_COST
+    } else {
+        STRAIGHT_COST
+    }
+}
+
+/// Straight line distance between two cells in the same fixed-point units as the step costs.
+pub fn euclidean_distance(a: Cell, b: Cell) -> u64 {
+    let dx = a[0] as f64 - b[0] as f64;
+    let dy = a[1] as f64 - b[1] as f64;
+    ((dx * dx + dy * dy).sqrt() * STRAIGHT_COST as f64) as u64
+}
+
+/// Octile distance between two cells in the same fixed-point units as the step costs.
+///
+/// This is the exact cost of an unobstructed 8-connected path, so it never overestimates.
+pub fn octile_distance(a: Cell, b: Cell) -> u64 {
+    let dx = (a[0] as i64 - b[0] as i64).abs() as u64;
+    let dy = (a[1] as i64 - b[1] as i64).abs() as u64;
+    let (min, max) = if dx < dy { (dx, dy) } else { (dy, dx) };
+    DIAGONAL_COST * min + STRAIGHT_COST * (max - min)
+}
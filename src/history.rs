@@ -0,0 +1,291 @@
+use rocket::State;
+use rocket_contrib::JSON;
+use std::collections::VecDeque;
+use std::mem;
+use error::Error;
+use journal::Author;
+use revision::{IfMatch, Tagged, Version};
+use snapshot::Snapshot;
+use storage::Record;
+
+/// Versions of a problem kept to undo and redo changes, before the oldest are forgotten.
+const MAX_HISTORY: usize = 100;
+
+/// Obstacles kept across every version in a history, so problems with many obstacles keep
+/// fewer versions rather than more memory.
+const MAX_HISTORY_OBSTACLES: usize = 100000;
+
+/// The earlier and undone versions of a problem.
+///
+/// History is only kept in memory, so it starts empty whenever the server starts.
+#[derive(Default)]
+pub struct History {
+    /// Versions before the current one, oldest first.
+    undo: VecDeque<Entry>,
+    /// Versions that were undone, the next one to redo last.
+    redo: Vec<Entry>,
+    /// Earlier revisions that the current version brought back.
+    restored: Vec<u64>,
+    /// Obstacles in every version kept.
+    obstacles: usize,
+}
+
+/// A version kept in the history.
+struct Entry {
+    record: Record,
+    /// Earlier revisions that this one brought back, which can still be found by their own
+    /// numbers.
+    restored: Vec<u64>,
+}
+
+impl History {
+    /// Remembers the version a change was made on, which can't be redone past any more.
+    pub fn record(&mut self, before: Record) {
+        for entry in self.redo.drain(..) {
+            self.obstacles -= entry.record.problem.obstacles.len();
+        }
+        self.obstacles += before.problem.obstacles.len();
+        let entry = Entry { record: before, restored: mem::replace(&mut self.restored, vec![]) };
+        self.push(entry);
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.undo.push_back(entry);
+        self.trim();
+    }
+
+    /// Forgets the oldest versions, and then the furthest undone ones, until the history fits.
+    fn trim(&mut self) {
+        while self.undo.len() + self.redo.len() > MAX_HISTORY ||
+              self.obstacles > MAX_HISTORY_OBSTACLES {
+            let forgotten = match self.undo.pop_front() {
+                Some(entry) => entry,
+                None if !self.redo.is_empty() => self.redo.remove(0),
+                None => break,
+            };
+            self.obstacles -= forgotten.record.problem.obstacles.len();
+        }
+    }
+
+    /// Swaps the current version for the one before it.
+    pub fn undo(&mut self, current: Record) -> Option<Record> {
+        match self.undo.pop_back() {
+            Some(previous) => {
+                let (replaced, previous) = self.swap(current, previous);
+                self.redo.push(replaced);
+                self.trim();
+                Some(previous)
+            }
+            None => None,
+        }
+    }
+
+    /// Swaps the current version for the last one undone.
+    pub fn redo(&mut self, current: Record) -> Option<Record> {
+        match self.redo.pop() {
+            Some(next) => {
+                let (replaced, next) = self.swap(current, next);
+                self.push(replaced);
+                Some(next)
+            }
+            None => None,
+        }
+    }
+
+    /// Takes `entry` out of the history to become the current version, and makes an entry
+    /// for the version it replaces.
+    fn swap(&mut self, current: Record, entry: Entry) -> (Entry, Record) {
+        self.obstacles -= entry.record.problem.obstacles.len();
+        self.obstacles += current.problem.obstacles.len();
+        let replaced = Entry {
+            record: current,
+            restored: mem::replace(&mut self.restored, entry.restored),
+        };
+        self.restored.push(entry.record.revision);
+        (replaced, entry.record)
+    }
+
+    /// The version that `undo` goes back to.
+    pub fn previous(&self) -> Option<&Record> {
+        self.undo.back().map(|entry| &entry.record)
+    }
+
+    /// The version that `redo` goes forward to.
+    pub fn next(&self) -> Option<&Record> {
+        self.redo.last().map(|entry| &entry.record)
+    }
+
+    /// Whether the current version brought back an earlier revision.
+    pub fn restores(&self, revision: u64) -> bool {
+        self.restored.contains(&revision)
+    }
+
+    /// A version that is still remembered, under its own revision or one it brought back.
+    pub fn find(&self, revision: u64) -> Option<&Record> {
+        self.undo.iter()
+            .chain(self.redo.iter())
+            .find(|entry| entry.record.revision == revision || entry.restored.contains(&revision))
+            .map(|entry| &entry.record)
+    }
+}
+
+/// The revisions a problem can be taken back or forward to.
+#[derive(Serialize)]
+struct Revisions {
+    revision: u64,
+    /// Revisions that `Undo` goes back to, the next one first.
+    undo: Vec<u64>,
+    /// Revisions that `Redo` goes forward to, the next one first.
+    redo: Vec<u64>,
+}
+
+#[get("/<problem>/History")]
+fn get(state: State<super::State>, problem: &str) -> Result<Tagged<JSON<Revisions>>, Error> {
+    // Attempt to access the problem.
+    state.history(problem, |history, problem| {
+        let revisions = Revisions {
+            revision: problem.revision,
+            undo: history.undo.iter().rev().map(|entry| entry.record.revision).collect(),
+            redo: history.redo.iter().rev().map(|entry| entry.record.revision).collect(),
+        };
+        Ok(Tagged(JSON(revisions), problem.version()))
+    })
+}
+
+#[get("/<problem>/Revisions/<revision>")]
+fn get_revision(state: State<super::State>, problem: &str, revision: u64)
+        -> Result<Tagged<JSON<Snapshot>>, Error> {
+    // Attempt to access the problem.
+    state.history(problem, |history, problem| {
+        let tag = Version { created: problem.metadata.created, revision: revision };
+        if revision == problem.revision || history.restores(revision) {
+            return Ok(Tagged(JSON(Snapshot::of(problem)), tag));
+        }
+        history.find(revision)
+            .map(|version| Tagged(JSON(version.problem.clone()), tag))
+            .ok_or_else(|| {
+                Error::not_found("revision",
+                                 format!("revision {} is not in the history", revision))
+            })
+    })
+}
+
+#[post("/<problem>/Undo")]
+fn undo(state: State<super::State>, if_match: IfMatch, author: Author, problem: &str)
+        -> Result<Tagged<()>, Error> {
+    let version = state.undo(problem, &author, |problem| if_match.check(problem))?;
+    Ok(Tagged((), version))
+}
+
+#[post("/<problem>/Redo")]
+fn redo(state: State<super::State>, if_match: IfMatch, author: Author, problem: &str)
+        -> Result<Tagged<()>, Error> {
+    let version = state.redo(problem, &author, |problem| if_match.check(problem))?;
+    Ok(Tagged((), version))
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType};
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Undo on "test" before "test" exists so it should fail.
+        let mut request = MockRequest::new(Method::Post, "/test/Undo");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Add "test" with an obstacle, then delete the obstacle by mistake.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/test/Obstacles/wall")
+            .header(ContentType::JSON)
+            .body(r#"{ "width": 1.0, "length": 5.0, "point": { "x": 4.0, "y": 0.0 } }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Delete, "/test/Obstacles/wall");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // There is nothing to redo yet.
+        let mut request = MockRequest::new(Method::Post, "/test/Redo");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Conflict);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "no_history");
+
+        // Undoing brings the obstacle back as a new revision.
+        let mut request = MockRequest::new(Method::Post, "/test/Undo");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("ETag").unwrap().ends_with("-3\""));
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles/wall");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // The history lists the revisions either side.
+        let mut request = MockRequest::new(Method::Get, "/test/History");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["revision"].as_u64(), Some(3));
+        assert_eq!(revisions(&body["undo"]), vec![0]);
+        assert_eq!(revisions(&body["redo"]), vec![2]);
+
+        // Old revisions can be read without going back to them.
+        let mut request = MockRequest::new(Method::Get, "/test/Revisions/0");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["obstacles"].as_object().unwrap().len(), 0);
+
+        // A revision that was undone to can still be found by its own number.
+        let mut request = MockRequest::new(Method::Get, "/test/Revisions/1");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["obstacles"].as_object().unwrap().len(), 1);
+
+        // Redoing deletes the obstacle again.
+        let mut request = MockRequest::new(Method::Post, "/test/Redo");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles/wall");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // A new change can't be redone past.
+        let mut request = MockRequest::new(Method::Post, "/test/Undo");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/test/Robot")
+            .header(ContentType::JSON)
+            .body(r#"{ "point": { "x": 1.0, "y": 1.0 }, "radius": 0.5 }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/test/Redo");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Conflict);
+    }
+
+    fn revisions(list: &serde_json::Value) -> Vec<u64> {
+        list.as_array().unwrap().iter().map(|revision| revision.as_u64().unwrap()).collect()
+    }
+}
@@ -0,0 +1,413 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::State;
+use rocket_contrib::JSON;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use super::Problem;
+use error::Error;
+use path::{self, PathOptions, PathResponse};
+use planner::{self, Budget};
+
+/// Finished jobs kept per problem before the oldest are forgotten.
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Jobs of a problem that may be queued or running at once.
+const MAX_UNFINISHED_JOBS: usize = 10;
+
+/// Jobs that may wait for a worker across all problems, each holding a copy of its problem.
+const MAX_QUEUED_JOBS: usize = 100;
+
+/// How far a planning job has got.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum JobStatus {
+    #[serde(rename = "queued")]
+    Queued,
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "done")]
+    Done,
+    #[serde(rename = "failed")]
+    Failed,
+    #[serde(rename = "cancelled")]
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        match *self {
+            JobStatus::Queued | JobStatus::Running => false,
+            _ => true,
+        }
+    }
+}
+
+/// What a client sees of a job.
+#[derive(Clone, Serialize)]
+struct JobReport {
+    id: usize,
+    status: JobStatus,
+    /// The revision of the problem the path is planned on.
+    revision: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Error>,
+}
+
+struct Job {
+    report: JobReport,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// A job waiting for a worker, along with everything needed to run it.
+struct Task {
+    job: Arc<Mutex<Job>>,
+    problem: Problem,
+    options: PathOptions,
+}
+
+/// Limits that apply to every job, read from `Rocket.toml`.
+#[derive(Clone)]
+pub struct JobLimits {
+    pub max_grid_cells: usize,
+    pub timeout: Option<Duration>,
+    pub max_expansions: Option<usize>,
+}
+
+/// The planning jobs of every problem, and the pool of workers running them.
+pub struct Jobs {
+    /// Jobs by problem and then by ID.
+    jobs: Mutex<HashMap<String, BTreeMap<usize, Arc<Mutex<Job>>>>>,
+    next_id: AtomicUsize,
+    queue: Mutex<SyncSender<Task>>,
+    limits: JobLimits,
+}
+
+/// Locks a mutex, ignoring poisoning since every update leaves what it guards consistent.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Jobs {
+    /// Starts `workers` threads, or at least one, that each run a job at a time.
+    pub fn new(workers: usize, limits: JobLimits) -> Jobs {
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_JOBS);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..cmp::max(workers, 1) {
+            let receiver = receiver.clone();
+            let limits = limits.clone();
+            thread::spawn(move || work(&receiver, &limits));
+        }
+        Jobs {
+            jobs: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(1),
+            queue: Mutex::new(sender),
+            limits: limits,
+        }
+    }
+
+    /// Moves the jobs of a problem along with it when it is renamed.
+    pub fn rename(&self, problem: &str, to: &str) {
+        let mut all = lock(&self.jobs);
+        if let Some(problem_jobs) = all.remove(problem) {
+            all.insert(String::from(to), problem_jobs);
+        }
+    }
+
+    /// Cancels and forgets every job of a problem that was deleted.
+    pub fn remove(&self, problem: &str) {
+        let problem_jobs = lock(&self.jobs).remove(problem);
+        for job in problem_jobs.iter().flat_map(|jobs| jobs.values()) {
+            let mut job = lock(job);
+            if !job.report.status.is_finished() {
+                job.report.status = JobStatus::Cancelled;
+                job.cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn forget(&self, problem: &str, id: usize) {
+        if let Some(problem_jobs) = lock(&self.jobs).get_mut(problem) {
+            problem_jobs.remove(&id);
+        }
+    }
+
+    fn job(&self, problem: &str, id: usize) -> Result<Arc<Mutex<Job>>, Error> {
+        lock(&self.jobs).get(problem)
+            .and_then(|jobs| jobs.get(&id))
+            .cloned()
+            .ok_or_else(|| Error::not_found("job_id", format!("job {} does not exist", id)))
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Task>>, limits: &JobLimits) {
+    loop {
+        let task = match lock(receiver).recv() {
+            Ok(task) => task,
+            // The server has shut down.
+            Err(_) => return,
+        };
+        let budget = {
+            let mut job = lock(&task.job);
+            if job.report.status == JobStatus::Cancelled {
+                continue;
+            }
+            job.report.status = JobStatus::Running;
+            Budget {
+                max_expansions: limits.max_expansions,
+                deadline: limits.timeout.map(planner::deadline),
+                cancelled: Some(job.cancelled.clone()),
+            }
+        };
+
+        // A panicking search fails its job rather than taking the worker down with it.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            path::search(&task.problem, &task.options, limits.max_grid_cells, budget)
+        }));
+        let mut job = lock(&task.job);
+        if job.report.status == JobStatus::Cancelled {
+            continue;
+        }
+        match result {
+            Ok(Ok((path, _))) => {
+                job.report.status = JobStatus::Done;
+                job.report.path = Some(path);
+            }
+            Ok(Err(error)) => {
+                job.report.status = JobStatus::Failed;
+                job.report.error = Some(error);
+            }
+            Err(_) => {
+                job.report.status = JobStatus::Failed;
+                job.report.error = Some(Error::new(Status::InternalServerError,
+                                                   "internal",
+                                                   "the search failed unexpectedly"));
+            }
+        }
+    }
+}
+
+#[post("/<problem>/PlanJobs?<options>", rank = 1)]
+fn post(state: State<super::State>,
+        jobs: State<Jobs>,
+        problem: &str,
+        options: PathOptions) -> Result<status::Custom<JSON<JobReport>>, Error> {
+    submit(&state, &jobs, problem, options)
+}
+
+#[post("/<problem>/PlanJobs", rank = 2)]
+fn post_default(state: State<super::State>,
+                jobs: State<Jobs>,
+                problem: &str) -> Result<status::Custom<JSON<JobReport>>, Error> {
+    submit(&state, &jobs, problem, PathOptions::default())
+}
+
+fn submit(state: &super::State, jobs: &Jobs, name: &str, options: PathOptions)
+        -> Result<status::Custom<JSON<JobReport>>, Error> {
+    // The job plans on the problem as it is now, and bad options are rejected right away.
+    let problem = state.snapshot(name)?;
+    path::check(&problem, &options, jobs.limits.max_grid_cells)?;
+
+    let id = jobs.next_id.fetch_add(1, Ordering::SeqCst);
+    let report = JobReport {
+        id: id,
+        status: JobStatus::Queued,
+        revision: problem.revision,
+        path: None,
+        error: None,
+    };
+    let job = Arc::new(Mutex::new(Job {
+        report: report.clone(),
+        cancelled: Arc::new(AtomicBool::new(false)),
+    }));
+    {
+        let mut all = lock(&jobs.jobs);
+        let problem_jobs = all.entry(String::from(name)).or_insert_with(BTreeMap::new);
+        let unfinished = problem_jobs.values()
+            .filter(|existing| !lock(existing).report.status.is_finished())
+            .count();
+        if unfinished >= MAX_UNFINISHED_JOBS {
+            return Err(Error::new(Status::ServiceUnavailable,
+                                  "too_many_jobs",
+                                  format!("the problem already has {} jobs that haven't \
+                                           finished", unfinished)));
+        }
+        problem_jobs.insert(id, job.clone());
+
+        // Forget the oldest finished jobs.
+        let finished: Vec<usize> = problem_jobs.iter()
+            .filter(|&(_, existing)| lock(existing).report.status.is_finished())
+            .map(|(&id, _)| id)
+            .collect();
+        if finished.len() > MAX_FINISHED_JOBS {
+            for id in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+                problem_jobs.remove(id);
+            }
+        }
+    }
+    let sent = lock(&jobs.queue).try_send(Task { job: job, problem: problem, options: options });
+    if let Err(error) = sent {
+        jobs.forget(name, id);
+        return Err(match error {
+            TrySendError::Full(_) => {
+                Error::new(Status::ServiceUnavailable,
+                           "queue_full",
+                           "too many jobs are waiting for a worker, try again later")
+            }
+            TrySendError::Disconnected(_) => {
+                Error::new(Status::ServiceUnavailable,
+                           "unavailable",
+                           "no workers are available to run the job")
+            }
+        });
+    }
+    Ok(status::Custom(Status::Accepted, JSON(report)))
+}
+
+#[get("/<problem>/PlanJobs/<id>")]
+fn get(jobs: State<Jobs>, problem: &str, id: usize) -> Result<JSON<JobReport>, Error> {
+    let job = jobs.job(problem, id)?;
+    let report = lock(&job).report.clone();
+    Ok(JSON(report))
+}
+
+/// Cancels a job that hasn't finished, and forgets it either way.
+#[delete("/<problem>/PlanJobs/<id>")]
+fn delete(jobs: State<Jobs>, problem: &str, id: usize) -> Result<(), Error> {
+    let job = jobs.job(problem, id)?;
+    {
+        let mut job = lock(&job);
+        if !job.report.status.is_finished() {
+            job.report.status = JobStatus::Cancelled;
+            job.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+    jobs.forget(problem, id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType};
+    use std::thread;
+    use std::time::Duration;
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Submit a job for "test" before "test" exists so it should fail.
+        let mut request = MockRequest::new(Method::Post, "/test/PlanJobs");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Add "test" with a robot, goal and boundary.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Put, "/test/Snapshot")
+            .header(ContentType::JSON)
+            .body(r#"{
+                "robot": { "point": { "x": 1.0, "y": 1.0 }, "radius": 0.5 },
+                "goal": { "point": { "x": 9.0, "y": 9.0 } },
+                "boundary": { "width": 10.0, "length": 10.0, "point": { "x": 0.0, "y": 0.0 } }
+            }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Bad options are rejected before a job is made.
+        let mut request = MockRequest::new(Method::Post, "/test/PlanJobs?algorithm=magic");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Submit a job and poll it until it is done.
+        let mut request = MockRequest::new(Method::Post, "/test/PlanJobs?algorithm=astar");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Accepted);
+        let job = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(job["status"], "queued");
+        let id = job["id"].as_u64().unwrap();
+        let mut status = String::new();
+        for _ in 0..500 {
+            let mut request = MockRequest::new(Method::Get, format!("/test/PlanJobs/{}", id));
+            let mut response = request.dispatch_with(&rocket);
+            assert_eq!(response.status(), Status::Ok);
+            let job = body_deser::<serde_json::Value>(&mut response).unwrap();
+            status = String::from(job["status"].as_str().unwrap());
+            if status == "done" {
+                assert!(job["path"]["points"].as_array().unwrap().len() > 1);
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(status, "done");
+
+        // The job follows the problem when it is renamed, and isn't seen by a new problem under
+        // the old name.
+        let mut request = MockRequest::new(Method::Post, "/test/Rename?to=moved");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, format!("/test/PlanJobs/{}", id));
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let mut request = MockRequest::new(Method::Get, format!("/moved/PlanJobs/{}", id));
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Deleting the problem drops its jobs too.
+        let mut request = MockRequest::new(Method::Delete, "/moved");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/moved");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, format!("/moved/PlanJobs/{}", id));
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let mut request = MockRequest::new(Method::Put, "/test/Snapshot")
+            .header(ContentType::JSON)
+            .body(r#"{
+                "robot": { "point": { "x": 1.0, "y": 1.0 }, "radius": 0.5 },
+                "goal": { "point": { "x": 9.0, "y": 9.0 } },
+                "boundary": { "width": 10.0, "length": 10.0, "point": { "x": 0.0, "y": 0.0 } }
+            }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/test/PlanJobs?algorithm=astar");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Accepted);
+        let id = body_deser::<serde_json::Value>(&mut response).unwrap()["id"].as_u64().unwrap();
+
+        // Deleting a job forgets it.
+        let mut request = MockRequest::new(Method::Delete, format!("/test/PlanJobs/{}", id));
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, format!("/test/PlanJobs/{}", id));
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
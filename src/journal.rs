@@ -0,0 +1,515 @@
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use super::Problem;
+use error::Error;
+use metadata::{self, Metadata};
+use operation::Operation;
+use settings::PlannerSettings;
+use snapshot::Snapshot;
+use storage::{storage_error, Record, Storage};
+
+/// Entries written to the journal before it is closed and compacted into a snapshot.
+const COMPACT_EVERY: u64 = 1000;
+
+/// Who made a change, from the `X-Author` header of the request, for the audit trail.
+#[derive(Clone, Debug, Default)]
+pub struct Author(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Author {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Author, ()> {
+        Outcome::Success(Author(request.headers().get_one("X-Author").map(String::from)))
+    }
+}
+
+/// A successful change to a problem, with enough detail to make it again.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Event {
+    #[serde(rename = "created")]
+    Created(Snapshot),
+    #[serde(rename = "deleted")]
+    Deleted,
+    #[serde(rename = "operation")]
+    Operation(Operation),
+    #[serde(rename = "batch")]
+    Batch(Vec<Operation>),
+    #[serde(rename = "planner")]
+    Planner(PlannerSettings),
+    #[serde(rename = "snapshot")]
+    Snapshot(Snapshot),
+    #[serde(rename = "metadata")]
+    Metadata(Metadata),
+    /// The problem was taken back or forward to a version in its history.
+    #[serde(rename = "restored")]
+    Restored(Record),
+    /// The problem was moved to a new name.
+    #[serde(rename = "renamed")]
+    Renamed(String),
+}
+
+impl Event {
+    /// Makes the change again on the problems it was first made on.
+    fn replay(self, problems: &mut HashMap<String, Problem>, name: &str) -> Result<(), Error> {
+        match self {
+            Event::Created(snapshot) => {
+                problems.insert(String::from(name), snapshot.into_problem());
+                Ok(())
+            }
+            Event::Deleted => {
+                problems.remove(name);
+                Ok(())
+            }
+            // Operations were checked when they were made, and aren't checked again.
+            Event::Operation(operation) => {
+                operation.replay(existing(problems, name)?);
+                Ok(())
+            }
+            Event::Batch(operations) => {
+                let problem = existing(problems, name)?;
+                for operation in operations {
+                    operation.replay(problem);
+                }
+                Ok(())
+            }
+            Event::Planner(settings) => {
+                existing(problems, name)?.planner = settings;
+                Ok(())
+            }
+            Event::Snapshot(snapshot) => {
+                let problem = existing(problems, name)?;
+                let metadata = problem.metadata.clone();
+                *problem = snapshot.into_problem();
+                problem.metadata = metadata;
+                Ok(())
+            }
+            Event::Restored(record) => {
+                *existing(problems, name)? = record.into_problem();
+                Ok(())
+            }
+            Event::Metadata(metadata) => {
+                let problem = existing(problems, name)?;
+                problem.metadata = Metadata {
+                    created: problem.metadata.created,
+                    modified: problem.metadata.modified,
+                    ..metadata
+                };
+                Ok(())
+            }
+            Event::Renamed(to) => {
+                let problem = problems.remove(name).ok_or_else(|| Error::problem_not_found(name))?;
+                problems.insert(to, problem);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn existing<'a>(problems: &'a mut HashMap<String, Problem>, name: &str)
+        -> Result<&'a mut Problem, Error> {
+    problems.get_mut(name).ok_or_else(|| Error::problem_not_found(name))
+}
+
+/// A change as the registry hands it to storage.
+pub struct Change<'a> {
+    pub author: &'a Author,
+    pub event: Event,
+}
+
+/// A line of the journal.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Position in the journal, counting from 1 and never reused.
+    sequence: u64,
+    /// When the change was made, in milliseconds since the Unix epoch.
+    time: u64,
+    problem: String,
+    /// The revision of the problem after the change.
+    revision: u64,
+    #[serde(default)]
+    author: Option<String>,
+    event: Event,
+}
+
+/// Every problem as of an entry of the journal, which replaces the entries up to it.
+#[derive(Default, Serialize, Deserialize)]
+struct Compacted {
+    sequence: u64,
+    problems: HashMap<String, Record>,
+}
+
+/// Keeps problems as an append-only journal of their changes.
+///
+/// The directory holds `snapshot.json`, the problems as of some entry, and `journal.jsonl`,
+/// the entries since. Every so often the journal is closed as `journal-<sequence>.jsonl`, after
+/// its last entry, and a new one is started. A background thread then compacts the closed
+/// journals into a new snapshot, and they are kept for auditing.
+pub struct Journal {
+    directory: PathBuf,
+    state: Arc<Mutex<JournalState>>,
+}
+
+#[derive(Default)]
+struct JournalState {
+    /// Opened on the first change, appending to what `load` found.
+    file: Option<File>,
+    /// The last entry in a closed journal.
+    closed: u64,
+    /// The last entry written.
+    sequence: u64,
+    /// The length to cut the journal back to, when part of an entry that failed to be written
+    /// couldn't be removed straight away.
+    torn: Option<u64>,
+    /// Whether the closed journals are being compacted.
+    compacting: bool,
+}
+
+fn lock(state: &Mutex<JournalState>) -> MutexGuard<JournalState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Journal {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Journal {
+        Journal {
+            directory: directory.into(),
+            state: Arc::new(Mutex::new(JournalState::default())),
+        }
+    }
+
+    /// Appends a change to the journal.
+    fn append(&self, name: &str, revision: u64, time: u64, change: &Change)
+              -> Result<(), Error> {
+        let mut state = lock(&self.state);
+        let entry = Entry {
+            sequence: state.sequence + 1,
+            time: time,
+            problem: String::from(name),
+            revision: revision,
+            author: change.author.0.clone(),
+            event: change.event.clone(),
+        };
+        let mut line = serde_json::to_vec(&entry).map_err(storage_error)?;
+        line.push(b'\n');
+        if state.file.is_none() {
+            fs::create_dir_all(&self.directory).map_err(storage_error)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.directory.join("journal.jsonl"))
+                .map_err(storage_error)?;
+            state.file = Some(file);
+        }
+        {
+            let state = &mut *state;
+            let file = state.file.as_mut().unwrap();
+            // Cut off what an earlier failed write left behind before it is followed by more.
+            if let Some(length) = state.torn {
+                file.set_len(length).map_err(storage_error)?;
+                state.torn = None;
+            }
+            // Only count the entry once it is safely on disk.
+            let length = file.metadata().map_err(storage_error)?.len();
+            if let Err(e) = file.write_all(&line).and_then(|_| file.sync_data()) {
+                if file.set_len(length).is_err() {
+                    state.torn = Some(length);
+                }
+                return Err(storage_error(e));
+            }
+        }
+        state.sequence = entry.sequence;
+
+        // The change is already safe in the journal, so a journal that fails to close is just
+        // closed after the next change, and one that isn't compacted is left to the next
+        // compaction.
+        if state.sequence - state.closed >= COMPACT_EVERY && self.close(&mut state).is_ok() &&
+           !state.compacting {
+            state.compacting = true;
+            let directory = self.directory.clone();
+            let shared = self.state.clone();
+            let through = state.closed;
+            thread::spawn(move || {
+                let _ = compact(&directory, through);
+                lock(&shared).compacting = false;
+            });
+        }
+        Ok(())
+    }
+
+    /// Closes the journal, so the next change starts a new one.
+    fn close(&self, state: &mut JournalState) -> Result<(), Error> {
+        state.file = None;
+        let closed = self.directory.join(format!("journal-{}.jsonl", state.sequence));
+        fs::rename(self.directory.join("journal.jsonl"), closed).map_err(storage_error)?;
+        state.closed = state.sequence;
+        Ok(())
+    }
+}
+
+/// Writes the problems as of the closed journals up to the one ending at `through` to a new
+/// snapshot.
+///
+/// The problems are read back from the last snapshot and the journals rather than kept in
+/// memory between compactions. The journals are never changed, so this doesn't need the lock.
+fn compact(directory: &Path, through: u64) -> Result<(), Error> {
+    let replayed = replay(directory, Some(through))?;
+    let json = serde_json::to_vec(&Compacted {
+        sequence: replayed.sequence,
+        problems: replayed.problems.iter()
+            .map(|(name, problem)| (name.clone(), Record::of(problem)))
+            .collect(),
+    }).map_err(storage_error)?;
+    // Closed journals up to the snapshot are skipped by `replay`, so a crash at any point
+    // loses nothing.
+    let snapshot = directory.join("snapshot.json");
+    let temporary = directory.join("snapshot.json.tmp");
+    File::create(&temporary)
+        .and_then(|mut out| out.write_all(&json).and_then(|_| out.sync_all()))
+        .and_then(|_| fs::rename(&temporary, &snapshot))
+        .map_err(storage_error)
+}
+
+/// The problems found by replaying the snapshot and the journals after it.
+struct Replayed {
+    /// The last entry in a closed journal.
+    closed: u64,
+    /// The last entry.
+    sequence: u64,
+    problems: HashMap<String, Problem>,
+}
+
+/// Replays the snapshot and the closed journals after it, up to the one ending at `through`
+/// or, without it, followed by the open journal.
+fn replay(directory: &Path, through: Option<u64>) -> Result<Replayed, Error> {
+    let compacted: Compacted = match File::open(directory.join("snapshot.json")) {
+        Ok(file) => serde_json::from_reader(file).map_err(storage_error)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Compacted::default(),
+        Err(e) => return Err(storage_error(e)),
+    };
+    let mut replayed = Replayed {
+        closed: compacted.sequence,
+        sequence: compacted.sequence,
+        problems: compacted.problems
+            .into_iter()
+            .map(|(name, record)| (name, record.into_problem()))
+            .collect(),
+    };
+    for (last, path) in closed_journals(directory)? {
+        if last <= compacted.sequence {
+            continue;
+        }
+        if through.map_or(false, |through| last > through) {
+            break;
+        }
+        replay_journal(&path, false, &mut replayed)?;
+        replayed.closed = last;
+    }
+    if through.is_none() {
+        replay_journal(&directory.join("journal.jsonl"), true, &mut replayed)?;
+    }
+    Ok(replayed)
+}
+
+/// The closed journals, along with their last entries, in order.
+fn closed_journals(directory: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(storage_error(e)),
+    };
+    let mut journals = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(storage_error)?.path();
+        let last = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.starts_with("journal-") && name.ends_with(".jsonl") => {
+                name["journal-".len()..name.len() - ".jsonl".len()].parse().ok()
+            }
+            _ => None,
+        };
+        if let Some(last) = last {
+            journals.push((last, path));
+        }
+    }
+    journals.sort();
+    Ok(journals)
+}
+
+/// Replays the entries of a journal after the ones already replayed.
+///
+/// A half-written last entry is cut off the journal if it is still `open`.
+fn replay_journal(path: &Path, open: bool, replayed: &mut Replayed) -> Result<(), Error> {
+    let mut text = String::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut text).map_err(storage_error)?;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(storage_error(e)),
+    }
+    let mut end = 0;
+    for line in text.split_terminator('\n') {
+        if open && end + line.len() == text.len() {
+            // The server stopped part way through writing the last entry, which was never
+            // acknowledged, so drop it before anything is appended after it.
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(end as u64))
+                .map_err(storage_error)?;
+            break;
+        }
+        end += line.len() + 1;
+        let entry: Entry = serde_json::from_str(line).map_err(storage_error)?;
+        if entry.sequence <= replayed.sequence {
+            continue;
+        }
+        let created = match entry.event {
+            Event::Created(_) => true,
+            _ => false,
+        };
+        entry.event.replay(&mut replayed.problems, &entry.problem)?;
+        // A problem that was deleted or renamed is no longer there to update.
+        if let Some(problem) = replayed.problems.get_mut(&entry.problem) {
+            problem.revision = entry.revision;
+            problem.metadata.modified = entry.time;
+            if created {
+                problem.metadata.created = entry.time;
+            }
+        }
+        replayed.sequence = entry.sequence;
+    }
+    Ok(())
+}
+
+impl Storage for Journal {
+    fn load(&self) -> Result<Vec<(String, Problem)>, Error> {
+        let mut state = lock(&self.state);
+        let replayed = replay(&self.directory, None)?;
+        state.closed = replayed.closed;
+        state.sequence = replayed.sequence;
+        Ok(replayed.problems.into_iter().collect())
+    }
+
+    fn save(&self, name: &str, problem: &Problem, change: &Change) -> Result<(), Error> {
+        // Use the time the problem was modified so replaying it gives the same metadata.
+        self.append(name, problem.revision, problem.metadata.modified, change)
+    }
+
+    fn remove(&self, name: &str, change: &Change) -> Result<(), Error> {
+        self.append(name, 0, metadata::now(), change)
+    }
+
+    fn rename(&self, name: &str, _: &str, problem: &Problem, change: &Change)
+              -> Result<(), Error> {
+        // The new name is in the entry's event.
+        self.append(name, problem.revision, metadata::now(), change)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proto;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::thread;
+    use std::time::Duration;
+    use super::{Author, Event, Journal, COMPACT_EVERY};
+    use operation::Operation;
+    use registry::Registry;
+    use storage::test_directory;
+
+    fn robot(x: f64) -> proto::Robot {
+        proto::Robot{
+            point: proto::Point{ x: x, y: 1.0 },
+            radius: 0.5,
+        }
+    }
+
+    #[test]
+    fn test() {
+        let directory = test_directory("journal-test");
+        let author = Author(Some(String::from("tester")));
+
+        // Every change is replayed when the server starts again.
+        let registry = Registry::new(Box::new(Journal::new(&directory))).unwrap();
+        registry.insert("test", Default::default(), &author).unwrap();
+        registry.insert("gone", Default::default(), &author).unwrap();
+        registry.change("test", &author, |problem| {
+            let operation = Operation::AddRobot(robot(1.0));
+            operation.clone().apply(problem)?;
+            problem.revision += 1;
+            Ok(((), Event::Operation(operation)))
+        }).unwrap();
+        registry.remove_if("gone", &author, |_| Ok(())).unwrap();
+        registry.rename("test", "moved", &author, |_| Ok(())).unwrap();
+        registry.rename("moved", "test", &author, |_| Ok(())).unwrap();
+        let modified = registry.read("test", |problem| Ok(problem.metadata.modified)).unwrap();
+
+        let registry = Registry::new(Box::new(Journal::new(&directory))).unwrap();
+        assert_eq!(registry.list().into_iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                   vec![String::from("test")]);
+        let problem = registry.snapshot("test").unwrap();
+        assert_eq!(problem.revision, 1);
+        assert_eq!(problem.robot.unwrap().point.x, 1.0);
+        assert_eq!(problem.metadata.modified, modified);
+
+        // Entries record who made the change.
+        let mut journal = String::new();
+        File::open(directory.join("journal.jsonl"))
+            .and_then(|mut file| file.read_to_string(&mut journal))
+            .unwrap();
+        assert_eq!(journal.lines().count(), 6);
+        assert!(journal.lines().all(|line| line.contains("\"author\":\"tester\"")));
+
+        // A half-written last entry is dropped rather than stopping the server from starting.
+        OpenOptions::new()
+            .append(true)
+            .open(directory.join("journal.jsonl"))
+            .and_then(|mut file| file.write_all(b"{\"sequence\":7,\"ti"))
+            .unwrap();
+        let registry = Registry::new(Box::new(Journal::new(&directory))).unwrap();
+        assert_eq!(registry.snapshot("test").unwrap().revision, 1);
+
+        // Enough changes compact the journal into a snapshot, which loads the same.
+        for x in 0..COMPACT_EVERY {
+            registry.change("test", &Author::default(), |problem| {
+                let operation = Operation::ReplaceRobot(robot(x as f64));
+                operation.clone().apply(problem)?;
+                problem.revision += 1;
+                Ok(((), Event::Operation(operation)))
+            }).unwrap();
+        }
+        // The journal is closed straight away, and compacted in the background.
+        assert!(directory.join(format!("journal-{}.jsonl", COMPACT_EVERY)).exists());
+        for _ in 0..500 {
+            if directory.join("snapshot.json").exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(directory.join("snapshot.json").exists());
+        let registry = Registry::new(Box::new(Journal::new(&directory))).unwrap();
+        let problem = registry.snapshot("test").unwrap();
+        assert_eq!(problem.revision, COMPACT_EVERY + 1);
+        assert_eq!(problem.robot.unwrap().point.x, (COMPACT_EVERY - 1) as f64);
+
+        // Entries that wouldn't pass today's checks, such as ones written before a check was
+        // added, still load.
+        registry.change("test", &Author::default(), |problem| {
+            let robot = proto::Robot{ radius: -1.0, ..robot(2.0) };
+            problem.robot = Some(robot.clone());
+            problem.revision += 1;
+            Ok(((), Event::Operation(Operation::ReplaceRobot(robot))))
+        }).unwrap();
+        let registry = Registry::new(Box::new(Journal::new(&directory))).unwrap();
+        assert_eq!(registry.snapshot("test").unwrap().robot.unwrap().radius, -1.0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -7,6 +7,10 @@ extern crate rocket_contrib;
 extern crate pathfinding;
 extern crate ndarray;
 extern crate itertools;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 
 mod obstacles;
 mod problems;
@@ -14,39 +18,219 @@ mod robot;
 mod goal;
 mod path;
 mod boundary;
+mod grid;
+mod planner;
+mod smooth;
+mod settings;
+mod error;
+mod diagnose;
+mod validate;
+mod spatial;
+mod snapshot;
+mod operation;
+mod revision;
+mod registry;
+mod dstar;
+mod jobs;
+mod storage;
+mod journal;
+mod history;
+mod templates;
+mod metadata;
 
 use std::collections::hash_map::HashMap;
-use std::sync::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
 
-type State = Mutex<HashMap<String, Problem>>;
+type State = registry::Registry;
 
-#[derive(Default)]
+/// Grid size limit used when `max_grid_cells` isn't set in `Rocket.toml`.
+const DEFAULT_MAX_GRID_CELLS: usize = 16_000_000;
+
+/// Number of threads running planning jobs when `plan_workers` isn't set in `Rocket.toml`.
+const DEFAULT_PLAN_WORKERS: usize = 2;
+
+/// Where problems are kept when `storage` isn't set in `Rocket.toml`.
+const DEFAULT_STORAGE: &'static str = "memory";
+
+/// Directory of the `json` and `journal` storage when `storage_path` isn't set in `Rocket.toml`.
+const DEFAULT_STORAGE_PATH: &'static str = "problems";
+
+/// Directory that templates are stored in when `templates_path` isn't set in `Rocket.toml`.
+const DEFAULT_TEMPLATES_PATH: &'static str = "templates";
+
+#[derive(Clone, Default)]
 struct Problem {
     obstacles: HashMap<String, proto::Obstacle>,
     robot: Option<proto::Robot>,
     goal: Option<proto::Goal>,
     boundary: Option<proto::Boundary>,
+    planner: settings::PlannerSettings,
+    /// Index of `obstacles`, kept in sync by `insert_obstacle` and `remove_obstacle`.
+    obstacle_index: spatial::SpatialIndex,
+    /// Bumped by every successful change, and sent to clients as part of the `ETag`.
+    revision: u64,
+    /// The occupancy grid of the last path request, kept up to date as obstacles change.
+    grid: Option<Arc<grid::Grid>>,
+    /// The search that `algorithm=dstar` path requests repair instead of starting over.
+    dstar: dstar::DStarLite,
+    metadata: metadata::Metadata,
+}
+
+impl Problem {
+    /// The revision of the problem, as sent to clients in the `ETag`.
+    fn version(&self) -> revision::Version {
+        revision::Version::of(self)
+    }
+
+    /// Adds or replaces an obstacle, returning the one it replaced.
+    fn insert_obstacle(&mut self, id: String, obstacle: proto::Obstacle)
+            -> Option<proto::Obstacle> {
+        let old = self.remove_obstacle(&id);
+        self.obstacle_index.insert(&id, &obstacle);
+        self.dstar.obstacle_changed(&obstacle);
+        self.update_grid(|grid| grid.add_obstacle(&obstacle));
+        self.obstacles.insert(id, obstacle);
+        self.obstacle_index.resize(&self.obstacles);
+        old
+    }
+
+    fn remove_obstacle(&mut self, id: &str) -> Option<proto::Obstacle> {
+        let old = self.obstacles.remove(id);
+        if let Some(ref obstacle) = old {
+            self.obstacle_index.remove(id, obstacle);
+            self.dstar.obstacle_changed(obstacle);
+            self.update_grid(|grid| grid.remove_obstacle(obstacle));
+            self.obstacle_index.resize(&self.obstacles);
+        }
+        old
+    }
+
+    /// Updates the cached grid in place, or drops it if a path request is still using it,
+    /// since copying a whole grid costs more than building it again.
+    fn update_grid<F: FnOnce(&mut grid::Grid)>(&mut self, f: F) {
+        let shared = match self.grid {
+            Some(ref mut grid) => {
+                match Arc::get_mut(grid) {
+                    Some(grid) => {
+                        f(grid);
+                        false
+                    }
+                    None => true,
+                }
+            }
+            None => false,
+        };
+        if shared {
+            self.grid = None;
+        }
+    }
 }
 
 fn new_mounted_rocket() -> rocket::Rocket {
-    rocket::ignite().mount("/",
+    let rocket = rocket::ignite();
+    let setting = |name: &str| {
+        rocket::config::active()
+            .and_then(|config| config.get_int(name).ok())
+            .map(|value| {
+                if value < 0 {
+                    panic!("`{}` in Rocket.toml must not be negative", name);
+                }
+                value as u64
+            })
+    };
+    let max_grid_cells = setting("max_grid_cells")
+        .map_or(DEFAULT_MAX_GRID_CELLS, |cells| cells as usize);
+    let plan_workers = setting("plan_workers").map_or(DEFAULT_PLAN_WORKERS, |n| n as usize);
+    let path_limits = path::PathLimits {
+        max_grid_cells: max_grid_cells,
+        default_timeout: setting("path_default_timeout_ms").map(Duration::from_millis),
+        max_timeout: setting("path_max_timeout_ms").map(Duration::from_millis),
+        default_expansions: setting("path_default_expansions").map(|n| n as usize),
+        max_expansions: setting("path_max_expansions").map(|n| n as usize),
+    };
+    let job_limits = jobs::JobLimits {
+        max_grid_cells: max_grid_cells,
+        timeout: setting("job_timeout_ms").map(Duration::from_millis),
+        max_expansions: setting("job_max_expansions").map(|n| n as usize),
+    };
+    let text = |name: &str| {
+        rocket::config::active()
+            .and_then(|config| config.get_str(name).ok())
+            .map(String::from)
+    };
+    let storage_name = text("storage").unwrap_or_else(|| String::from(DEFAULT_STORAGE));
+    let storage_path = text("storage_path").unwrap_or_else(|| String::from(DEFAULT_STORAGE_PATH));
+    let storage = storage::by_name(&storage_name, &storage_path)
+        .unwrap_or_else(|| panic!("unknown storage \"{}\" in Rocket.toml", storage_name));
+    let registry = registry::Registry::new(storage)
+        .unwrap_or_else(|e| panic!("failed to load problems: {}", e.message));
+    let templates_path = text("templates_path")
+        .unwrap_or_else(|| String::from(DEFAULT_TEMPLATES_PATH));
+    let template_storage = storage::by_name(&storage_name, &templates_path)
+        .unwrap_or_else(|| panic!("unknown storage \"{}\" in Rocket.toml", storage_name));
+    let templates = registry::Registry::new(template_storage)
+        .unwrap_or_else(|e| panic!("failed to load templates: {}", e.message));
+
+    rocket.mount("/",
                            routes![
+        problems::get_query,
         problems::get,
+        problems::post_from,
         problems::post,
+        problems::clone_to,
+        problems::rename,
         problems::delete,
         obstacles::get_all,
+        obstacles::get_query,
+        obstacles::get,
         obstacles::delete,
         obstacles::post,
         obstacles::put,
+        robot::get,
+        robot::delete,
         robot::post,
         robot::put,
+        goal::get,
+        goal::delete,
         goal::post,
         goal::put,
+        boundary::get,
+        boundary::delete,
         boundary::post,
         boundary::put,
         path::get,
+        path::get_default,
+        settings::get,
+        settings::put,
+        metadata::get,
+        metadata::put,
+        snapshot::get,
+        snapshot::put,
+        operation::batch,
+        templates::get_all,
+        templates::get,
+        templates::put,
+        templates::delete,
+        history::get,
+        history::get_revision,
+        history::undo,
+        history::redo,
+        jobs::post,
+        jobs::post_default,
+        jobs::get,
+        jobs::delete,
+        ])
+        .catch(errors![
+        error::bad_request,
+        error::not_found,
+        error::unprocessable_entity,
+        error::internal_server_error,
         ])
-        .manage(Mutex::new(HashMap::<String, Problem>::new()))
+        .manage(registry)
+        .manage(templates::Templates::new(templates))
+        .manage(path_limits)
+        .manage(jobs::Jobs::new(plan_workers, job_limits))
 }
 
 fn main() {
@@ -0,0 +1,138 @@
+use rocket::State;
+use rocket_contrib::JSON;
+use std::collections::BTreeSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+use error::Error;
+use journal::{Author, Event};
+use revision::{IfMatch, Tagged};
+
+/// What people know a problem by, beyond its name.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// When the problem was created, in milliseconds since the Unix epoch.
+    ///
+    /// Set by the server, and ignored when metadata is sent.
+    #[serde(default)]
+    pub created: u64,
+    /// When the problem last changed, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub modified: u64,
+}
+
+/// The current time in milliseconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() * 1000 + (time.subsec_nanos() / 1_000_000) as u64)
+        .unwrap_or(0)
+}
+
+#[get("/<problem>/Metadata")]
+fn get(state: State<super::State>, problem: &str) -> Result<Tagged<JSON<Metadata>>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        Ok(Tagged(JSON(problem.metadata.clone()), problem.version()))
+    })
+}
+
+/// Replaces the description, owner and tags of a problem.
+#[put("/<problem>/Metadata", data = "<metadata>")]
+fn put(state: State<super::State>,
+       if_match: IfMatch,
+       author: Author,
+       problem: &str,
+       metadata: JSON<Metadata>) -> Result<Tagged<()>, Error> {
+    if metadata.tags.iter().any(|tag| tag.is_empty()) {
+        return Err(Error::invalid("tags", "tags can't be empty"));
+    }
+    // Attempt to access the problem.
+    state.change(problem, &author, |problem| {
+        if_match.check(problem)?;
+        let metadata = Metadata {
+            created: problem.metadata.created,
+            modified: problem.metadata.modified,
+            ..metadata.0
+        };
+        problem.metadata = metadata.clone();
+        problem.revision += 1;
+        Ok((Tagged((), problem.version()), Event::Metadata(metadata)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType};
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Get the metadata of "test" before "test" exists so it should fail.
+        let mut request = MockRequest::new(Method::Get, "/test/Metadata");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // A new problem knows when it was made.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Metadata");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let created = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert!(created["created"].as_u64().unwrap() > 0);
+        assert_eq!(created["created"], created["modified"]);
+        assert_eq!(created["tags"].as_array().unwrap().len(), 0);
+
+        // Describe it, ignoring any attempt to change the timestamps.
+        let mut request = MockRequest::new(Method::Put, "/test/Metadata")
+            .header(ContentType::JSON)
+            .body(r#"{
+                "description": "Base layout",
+                "owner": "ops",
+                "tags": ["warehouse", "base"],
+                "created": 1
+            }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let tag = format!("\"{}-1\"", created["created"]);
+        assert_eq!(response.headers().get_one("ETag"), Some(tag.as_str()));
+        let mut request = MockRequest::new(Method::Get, "/test/Metadata");
+        let mut response = request.dispatch_with(&rocket);
+        let metadata = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(metadata["description"], "Base layout");
+        assert_eq!(metadata["owner"], "ops");
+        assert_eq!(metadata["tags"][0], "base");
+        assert_eq!(metadata["created"], created["created"]);
+        assert!(metadata["modified"].as_u64() >= created["modified"].as_u64());
+
+        // Empty tags are rejected.
+        let mut request = MockRequest::new(Method::Put, "/test/Metadata")
+            .header(ContentType::JSON)
+            .body(r#"{ "tags": [""] }"#);
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["field"], "tags");
+    }
+}
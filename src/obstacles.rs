@@ -1,76 +1,193 @@
-use rocket::http::Status;
-use rocket::response::status;
+use rocket::response::{self, Responder};
 use rocket::State;
 use rocket_contrib::JSON;
-use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
 use proto;
+use super::Problem;
+use error::Error;
+use spatial::Rect;
+use operation::{self, Operation, obstacle_not_found};
+use journal::Author;
+use revision::{IfMatch, Tagged};
 
-#[get("/<problem>/Obstacles")]
-fn get_all(state: State<super::State>, problem: &str) -> Result<JSON<Vec<String>>, status::Custom<()>> {
-    // Attempt to access the problem.
-    match state.lock().unwrap().get_mut(problem) {
-        Some(problem) => Ok(JSON(problem.obstacles.keys().cloned().collect())),
-        None => Err(status::Custom(Status::NotFound, ())),
-    }
+/// Query parameters accepted when listing obstacles.
+#[derive(FromForm)]
+struct ObstacleQuery {
+    /// Return full obstacles keyed by ID instead of just the IDs.
+    expand: Option<bool>,
+    /// Only obstacles entirely inside the rectangle `x,y,width,length`.
+    within: Option<String>,
+    /// Only obstacles within `radius` of the point `x,y`.
+    near: Option<String>,
+    radius: Option<f64>,
 }
 
-#[delete("/<problem>/Obstacles/<obstacle_id>")]
-fn delete(state: State<super::State>, problem: &str, obstacle_id: &str) -> status::Custom<()> {
-    // Attempt to access the problem.
-    match state.lock().unwrap().get_mut(problem) {
-        Some(problem) => {
-            // Attempt to remove the obstacle.
-            match problem.obstacles.remove(obstacle_id) {
-                Some(_) => status::Custom(Status::Ok, ()),
-                None => status::Custom(Status::NotFound, ()),
-            }
+/// A listing of obstacles, either as IDs or as full obstacles keyed by their ID.
+enum Listing {
+    Ids(Vec<String>),
+    Expanded(BTreeMap<String, proto::Obstacle>),
+}
+
+impl<'r> Responder<'r> for Listing {
+    fn respond(self) -> response::Result<'r> {
+        match self {
+            Listing::Ids(ids) => JSON(ids).respond(),
+            Listing::Expanded(obstacles) => JSON(obstacles).respond(),
         }
-        None => status::Custom(Status::NotFound, ()),
     }
 }
 
+#[get("/<problem>/Obstacles", rank = 2)]
+fn get_all(state: State<super::State>, problem: &str)
+        -> Result<Tagged<JSON<Vec<String>>>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        Ok(Tagged(JSON(problem.obstacles.keys().cloned().collect()), problem.version()))
+    })
+}
+
+#[get("/<problem>/Obstacles?<query>", rank = 1)]
+fn get_query(state: State<super::State>, problem: &str, query: ObstacleQuery)
+        -> Result<Tagged<Listing>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        let ids = filter(problem, &query)?;
+        let listing = if query.expand.unwrap_or(false) {
+            Listing::Expanded(ids.into_iter()
+                .map(|id| {
+                    let obstacle = problem.obstacles[&id].clone();
+                    (id, obstacle)
+                })
+                .collect())
+        } else {
+            Listing::Ids(ids)
+        };
+        Ok(Tagged(listing, problem.version()))
+    })
+}
+
+#[get("/<problem>/Obstacles/<obstacle_id>")]
+fn get(state: State<super::State>, problem: &str, obstacle_id: &str)
+        -> Result<Tagged<JSON<proto::Obstacle>>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        problem.obstacles.get(obstacle_id)
+            .cloned()
+            .map(|obstacle| Tagged(JSON(obstacle), problem.version()))
+            .ok_or_else(|| obstacle_not_found(obstacle_id))
+    })
+}
+
+#[delete("/<problem>/Obstacles/<obstacle_id>")]
+fn delete(state: State<super::State>,
+          if_match: IfMatch,
+          author: Author,
+          problem: &str,
+          obstacle_id: &str) -> Result<Tagged<()>, Error> {
+    operation::apply(&state,
+                     problem,
+                     &if_match,
+                     &author,
+                     Operation::DeleteObstacle { id: String::from(obstacle_id) })
+}
+
 #[post("/<problem>/Obstacles/<obstacle_id>", data = "<obstacle>")]
 fn post(state: State<super::State>,
+        if_match: IfMatch,
+        author: Author,
         problem: &str,
         obstacle_id: &str,
-        obstacle: JSON<proto::Obstacle>) -> status::Custom<()> {
-    // Attempt to access the problem.
-    match state.lock().unwrap().get_mut(problem) {
-        Some(problem) => {
-            // Attempt to remove the obstacle.
-            match problem.obstacles.entry(String::from(obstacle_id)) {
-                Entry::Occupied(_) => status::Custom(Status::Conflict, ()),
-                Entry::Vacant(v) => {
-                    v.insert(obstacle.0);
-                    status::Custom(Status::Ok, ())
-                }
-            }
-        }
-        None => status::Custom(Status::NotFound, ()),
-    }
+        obstacle: JSON<proto::Obstacle>) -> Result<Tagged<()>, Error> {
+    operation::apply(&state,
+                     problem,
+                     &if_match,
+                     &author,
+                     Operation::AddObstacle {
+                         id: String::from(obstacle_id),
+                         obstacle: obstacle.0,
+                     })
 }
 
 #[put("/<problem>/Obstacles/<obstacle_id>", data = "<obstacle>")]
 fn put(state: State<super::State>,
-        problem: &str,
-        obstacle_id: &str,
-        obstacle: JSON<proto::Obstacle>) -> status::Custom<()> {
-    // Attempt to access the problem.
-    match state.lock().unwrap().get_mut(problem) {
-        Some(problem) => {
-            // Attempt to remove the obstacle.
-            match problem.obstacles.entry(String::from(obstacle_id)) {
-                Entry::Occupied(mut o) => {
-                    o.insert(obstacle.0);
-                    status::Custom(Status::Ok, ())
-                },
-                Entry::Vacant(_) => status::Custom(Status::NotFound, ()),
-            }
+       if_match: IfMatch,
+       author: Author,
+       problem: &str,
+       obstacle_id: &str,
+       obstacle: JSON<proto::Obstacle>) -> Result<Tagged<()>, Error> {
+    operation::apply(&state,
+                     problem,
+                     &if_match,
+                     &author,
+                     Operation::ReplaceObstacle {
+                         id: String::from(obstacle_id),
+                         obstacle: obstacle.0,
+                     })
+}
+
+/// Parses a comma separated list of exactly `count` finite numbers.
+fn parse_numbers(field: &str, value: &str, count: usize) -> Result<Vec<f64>, Error> {
+    let numbers = value.split(',')
+        .map(|n| n.trim().parse::<f64>().ok())
+        .map(|n| n.and_then(|n| if n.is_finite() { Some(n) } else { None }))
+        .collect::<Option<Vec<f64>>>();
+    match numbers {
+        Some(numbers) if numbers.len() == count => Ok(numbers),
+        _ => {
+            Err(Error::invalid(field,
+                               format!("{} must be {} comma separated numbers", field, count)))
         }
-        None => status::Custom(Status::NotFound, ()),
     }
 }
 
+/// The sorted IDs of the obstacles matching the spatial filters of a query.
+fn filter(problem: &Problem, query: &ObstacleQuery) -> Result<Vec<String>, Error> {
+    let within = match query.within {
+        Some(ref within) => {
+            let n = parse_numbers("within", within, 4)?;
+            Some(Rect { min: [n[0], n[1]], max: [n[0] + n[2], n[1] + n[3]] })
+        }
+        None => None,
+    };
+    let near = match (query.near.as_ref(), query.radius) {
+        (Some(near), Some(radius)) if radius >= 0.0 && radius.is_finite() => {
+            let n = parse_numbers("near", near, 2)?;
+            Some(([n[0], n[1]], radius))
+        }
+        (Some(_), _) => {
+            return Err(Error::invalid("radius", "near needs a non-negative radius"));
+        }
+        (None, Some(_)) => {
+            return Err(Error::invalid("near", "radius needs a point to be near"));
+        }
+        (None, None) => None,
+    };
+
+    // Narrow the candidates down with the index when there is an area to look in.
+    let area = match (within, near) {
+        (Some(rect), _) => Some(rect),
+        (None, Some((point, radius))) => Some(Rect {
+            min: [point[0] - radius, point[1] - radius],
+            max: [point[0] + radius, point[1] + radius],
+        }),
+        (None, None) => None,
+    };
+    let candidates: Vec<String> = match area {
+        Some(ref area) => problem.obstacle_index.candidates(area).into_iter().collect(),
+        None => problem.obstacles.keys().cloned().collect(),
+    };
+
+    let mut ids: Vec<String> = candidates.into_iter()
+        .filter(|id| {
+            let rect = Rect::of_obstacle(&problem.obstacles[id]);
+            within.map_or(true, |within| within.contains(&rect)) &&
+                near.map_or(true, |(point, radius)| rect.distance_to(point) <= radius)
+        })
+        .collect();
+    ids.sort();
+    Ok(ids)
+}
+
 #[cfg(test)]
 mod test {
     extern crate serde;
@@ -148,5 +265,71 @@ mod test {
         let mut response = request.dispatch_with(&rocket);
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(body_deser(&mut response), Some(vec![String::from("asd")]));
+
+        // Get obstacle "asd" on its own.
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles/asd");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let obstacle = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(obstacle["point"]["x"].as_f64(), Some(5.0));
+
+        // Getting an obstacle that doesn't exist fails.
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles/qwe");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Add obstacle "far" to "test", well away from "asd".
+        let mut request = MockRequest::new(Method::Post, "/test/Obstacles/far")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&proto::Obstacle{
+                length: 2.0,
+                width: 2.0,
+                point: proto::Point{ x: 50.0, y: 50.0 },
+            }).unwrap());
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Only "asd" lies entirely inside the rectangle around it.
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles?within=0,0,10,10");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_deser(&mut response), Some(vec![String::from("asd")]));
+
+        // "far" is within reach of a point next to it, and comes back in full when expanded.
+        let mut request =
+            MockRequest::new(Method::Get, "/test/Obstacles?near=49,51&radius=1.5&expand=true");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let obstacles = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(obstacles["far"]["width"].as_f64(), Some(2.0));
+        assert!(obstacles.get("asd").is_none());
+
+        // A point without a radius is rejected.
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles?near=49,51");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["field"], "radius");
+
+        // And a radius without a point.
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles?radius=1.5");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["field"], "near");
+
+        // So is a malformed rectangle.
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles?within=0,0,10");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Deleting an obstacle takes it out of spatial queries too.
+        let mut request = MockRequest::new(Method::Delete, "/test/Obstacles/asd");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles?within=0,0,10,10");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_deser::<Vec<String>>(&mut response), Some(vec![]));
     }
 }
@@ -0,0 +1,281 @@
+use rocket::State;
+use rocket_contrib::JSON;
+use proto;
+use super::Problem;
+use error::Error;
+use journal::{Author, Event};
+use revision::{IfMatch, Tagged};
+use validate;
+
+/// A single change to a problem, with the same meaning as the route that makes it.
+///
+/// `add_*` fails if the entity is already set, `replace_*` fails if it isn't, and `delete_*`
+/// fails if there's nothing to delete.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Operation {
+    #[serde(rename = "add_obstacle")]
+    AddObstacle { id: String, obstacle: proto::Obstacle },
+    #[serde(rename = "replace_obstacle")]
+    ReplaceObstacle { id: String, obstacle: proto::Obstacle },
+    #[serde(rename = "delete_obstacle")]
+    DeleteObstacle { id: String },
+    #[serde(rename = "add_robot")]
+    AddRobot(proto::Robot),
+    #[serde(rename = "replace_robot")]
+    ReplaceRobot(proto::Robot),
+    #[serde(rename = "delete_robot")]
+    DeleteRobot,
+    #[serde(rename = "add_goal")]
+    AddGoal(proto::Goal),
+    #[serde(rename = "replace_goal")]
+    ReplaceGoal(proto::Goal),
+    #[serde(rename = "delete_goal")]
+    DeleteGoal,
+    #[serde(rename = "add_boundary")]
+    AddBoundary(proto::Boundary),
+    #[serde(rename = "replace_boundary")]
+    ReplaceBoundary(proto::Boundary),
+    #[serde(rename = "delete_boundary")]
+    DeleteBoundary,
+}
+
+impl Operation {
+    /// Applies the operation, leaving the problem untouched if it fails.
+    pub fn apply(self, problem: &mut Problem) -> Result<(), Error> {
+        match self {
+            Operation::AddObstacle { ref id, ref obstacle } => {
+                if problem.obstacles.contains_key(id) {
+                    return Err(Error::already_exists("obstacle_id",
+                                                     format!("obstacle \"{}\" already exists",
+                                                             id)));
+                }
+                validate::obstacle(obstacle)?;
+            }
+            Operation::ReplaceObstacle { ref id, ref obstacle } => {
+                if !problem.obstacles.contains_key(id) {
+                    return Err(obstacle_not_found(id));
+                }
+                validate::obstacle(obstacle)?;
+            }
+            Operation::DeleteObstacle { ref id } => {
+                if !problem.obstacles.contains_key(id) {
+                    return Err(obstacle_not_found(id));
+                }
+            }
+            Operation::AddRobot(ref robot) => {
+                if problem.robot.is_some() {
+                    return Err(Error::already_set("robot"));
+                }
+                validate::robot(problem, robot)?;
+            }
+            Operation::ReplaceRobot(ref robot) => {
+                if problem.robot.is_none() {
+                    return Err(Error::not_set("robot"));
+                }
+                validate::robot(problem, robot)?;
+            }
+            Operation::DeleteRobot => {
+                if problem.robot.is_none() {
+                    return Err(Error::unset("robot"));
+                }
+            }
+            Operation::AddGoal(ref goal) => {
+                if problem.goal.is_some() {
+                    return Err(Error::already_set("goal"));
+                }
+                validate::goal(problem, goal)?;
+            }
+            Operation::ReplaceGoal(ref goal) => {
+                if problem.goal.is_none() {
+                    return Err(Error::not_set("goal"));
+                }
+                validate::goal(problem, goal)?;
+            }
+            Operation::DeleteGoal => {
+                if problem.goal.is_none() {
+                    return Err(Error::unset("goal"));
+                }
+            }
+            Operation::AddBoundary(ref boundary) => {
+                if problem.boundary.is_some() {
+                    return Err(Error::already_set("boundary"));
+                }
+                validate::boundary(problem, boundary)?;
+            }
+            Operation::ReplaceBoundary(ref boundary) => {
+                if problem.boundary.is_none() {
+                    return Err(Error::not_set("boundary"));
+                }
+                validate::boundary(problem, boundary)?;
+            }
+            Operation::DeleteBoundary => {
+                if problem.boundary.is_none() {
+                    return Err(Error::unset("boundary"));
+                }
+            }
+        }
+        self.replay(problem);
+        Ok(())
+    }
+
+    /// Makes the change without checking it, for operations that were checked when they were
+    /// first applied. Old changes keep loading even if the checks have since become stricter.
+    pub fn replay(self, problem: &mut Problem) {
+        match self {
+            Operation::AddObstacle { id, obstacle } |
+            Operation::ReplaceObstacle { id, obstacle } => {
+                problem.insert_obstacle(id, obstacle);
+            }
+            Operation::DeleteObstacle { id } => {
+                problem.remove_obstacle(&id);
+            }
+            Operation::AddRobot(robot) | Operation::ReplaceRobot(robot) => {
+                problem.robot = Some(robot);
+            }
+            Operation::DeleteRobot => problem.robot = None,
+            Operation::AddGoal(goal) | Operation::ReplaceGoal(goal) => problem.goal = Some(goal),
+            Operation::DeleteGoal => problem.goal = None,
+            Operation::AddBoundary(boundary) | Operation::ReplaceBoundary(boundary) => {
+                problem.boundary = Some(boundary);
+            }
+            Operation::DeleteBoundary => problem.boundary = None,
+        }
+    }
+}
+
+pub fn obstacle_not_found(obstacle_id: &str) -> Error {
+    Error::not_found("obstacle_id", format!("obstacle \"{}\" does not exist", obstacle_id))
+}
+
+/// Applies a single operation to a problem on behalf of a route.
+pub fn apply(state: &super::State,
+             problem: &str,
+             if_match: &IfMatch,
+             author: &Author,
+             operation: Operation) -> Result<Tagged<()>, Error> {
+    // Attempt to access the problem.
+    state.change(problem, author, |problem| {
+        if_match.check(problem)?;
+        operation.clone().apply(problem)?;
+        problem.revision += 1;
+        Ok((Tagged((), problem.version()), Event::Operation(operation)))
+    })
+}
+
+/// Applies every operation in order as a single revision, or none of them if any fails.
+pub fn apply_all(problem: &mut Problem, operations: Vec<Operation>) -> Result<(), Error> {
+    // Work on a copy so a failure part way through leaves the problem as it was, apart from
+    // the caches, which are moved over rather than copied and dropped if an operation fails.
+    let mut updated = problem.clone();
+    updated.grid = problem.grid.take();
+    updated.dstar = problem.dstar.take();
+    for (index, operation) in operations.into_iter().enumerate() {
+        if let Err(mut error) = operation.apply(&mut updated) {
+            error.message = format!("operation {} failed: {}", index, error.message);
+            error.field = Some(match error.field {
+                Some(field) => format!("operations.{}.{}", index, field),
+                None => format!("operations.{}", index),
+            });
+            return Err(error);
+        }
+    }
+    updated.revision += 1;
+    *problem = updated;
+    Ok(())
+}
+
+#[post("/<problem>/Batch", data = "<operations>")]
+fn batch(state: State<super::State>,
+         if_match: IfMatch,
+         author: Author,
+         problem: &str,
+         operations: JSON<Vec<Operation>>) -> Result<Tagged<()>, Error> {
+    // Attempt to access the problem.
+    state.change(problem, &author, |problem| {
+        if_match.check(problem)?;
+        apply_all(problem, operations.0.clone())?;
+        Ok((Tagged((), problem.version()), Event::Batch(operations.0)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType};
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Send a batch to "test" before "test" exists so it should fail.
+        let mut request = MockRequest::new(Method::Post, "/test/Batch")
+            .header(ContentType::JSON)
+            .body("[]");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Add "test" to the problems.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Build a whole scenario in one request.
+        let mut request = MockRequest::new(Method::Post, "/test/Batch")
+            .header(ContentType::JSON)
+            .body(r#"[
+                { "add_boundary": {
+                    "width": 10.0, "length": 10.0, "point": { "x": 0.0, "y": 0.0 } } },
+                { "add_robot": { "point": { "x": 1.0, "y": 1.0 }, "radius": 0.5 } },
+                { "add_obstacle": { "id": "a", "obstacle": {
+                    "width": 1.0, "length": 1.0, "point": { "x": 5.0, "y": 5.0 } } } },
+                { "add_obstacle": { "id": "b", "obstacle": {
+                    "width": 1.0, "length": 1.0, "point": { "x": 7.0, "y": 7.0 } } } },
+                { "delete_obstacle": { "id": "b" } }
+            ]"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(body_deser(&mut response), Some(vec![String::from("a")]));
+
+        // The third operation conflicts, so the first two don't happen either.
+        let mut request = MockRequest::new(Method::Post, "/test/Batch")
+            .header(ContentType::JSON)
+            .body(r#"[
+                { "add_goal": { "point": { "x": 9.0, "y": 9.0 } } },
+                { "delete_obstacle": { "id": "a" } },
+                { "add_robot": { "point": { "x": 2.0, "y": 2.0 }, "radius": 0.5 } }
+            ]"#);
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Conflict);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "already_set");
+        assert_eq!(body["field"], "operations.2.robot");
+        let mut request = MockRequest::new(Method::Get, "/test/Goal");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles/a");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Deleting a missing obstacle fails the same way the route does.
+        let mut request = MockRequest::new(Method::Post, "/test/Batch")
+            .header(ContentType::JSON)
+            .body(r#"[{ "delete_obstacle": { "id": "missing" } }]"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
@@ -1,87 +1,425 @@
 use rocket::http::Status;
-use rocket::response::status;
+use rocket::request::{FormItems, FromForm, FromFormValue};
 use rocket::State;
 use rocket_contrib::JSON;
+use std::sync::Arc;
+use std::time::Duration;
 use proto;
 use super::Problem;
+use error::Error;
+use diagnose::{self, Diagnosis};
+use grid::{self, Grid};
+use planner::{self, Budget, Expansions, Planner, Stop};
+use revision::Tagged;
+use smooth::Smoothing;
 
-use itertools::Itertools;
+/// Query parameters accepted by the path route.
+#[derive(Clone, Default)]
+pub struct PathOptions {
+    /// Name of the planner to use, see `planner::by_name`, or `dstar` to carry on from the
+    /// previous D* Lite search of the problem.
+    ///
+    /// `dstar` requests on a problem run one at a time, each repairing the search the one
+    /// before it left. Planning jobs work on copies of a problem, which start a search of their
+    /// own.
+    algorithm: Option<String>,
+    /// Name of the smoothing stage to run afterwards, see `Smoothing::by_name`.
+    smooth: Option<String>,
+    /// Distance between the points of a spline, defaulting to the grid granularity, which is
+    /// also the smallest spacing allowed.
+    spacing: Option<f64>,
+    /// Size of a grid cell, overriding the resolution in the problem's planner settings.
+    resolution: Option<f64>,
+    /// What to do when the goal can't be reached, either `none` or `nearest`.
+    fallback: Option<String>,
+    /// How long the search may take, in milliseconds.
+    timeout_ms: Option<u64>,
+    /// How many cells the search may expand.
+    max_expansions: Option<usize>,
+    /// The first parameter that was unknown or couldn't be parsed.
+    invalid: Option<Error>,
+}
+
+impl<'f> FromForm<'f> for PathOptions {
+    type Error = ();
+
+    /// Never fails, keeping bad parameters in `invalid` instead. Failing would pass the request
+    /// on to the route without options, which would quietly plan with the defaults.
+    fn from_form_items(items: &mut FormItems<'f>) -> Result<PathOptions, ()> {
+        let mut options = PathOptions::default();
+        for (key, value) in items {
+            if let Err(error) = options.set(key, value) {
+                if options.invalid.is_none() {
+                    options.invalid = Some(error);
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl PathOptions {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "algorithm" => self.algorithm = Some(parameter(key, value)?),
+            "smooth" => self.smooth = Some(parameter(key, value)?),
+            "spacing" => self.spacing = Some(parameter(key, value)?),
+            "resolution" => self.resolution = Some(parameter(key, value)?),
+            "fallback" => self.fallback = Some(parameter(key, value)?),
+            "timeout_ms" => self.timeout_ms = Some(parameter(key, value)?),
+            "max_expansions" => self.max_expansions = Some(parameter(key, value)?),
+            _ => return Err(Error::unknown_parameter(key)),
+        }
+        Ok(())
+    }
+}
+
+/// Parses the value of a query parameter.
+fn parameter<'v, T: FromFormValue<'v>>(key: &str, value: &'v str) -> Result<T, Error> {
+    T::from_form_value(value)
+        .map_err(|_| Error::invalid(key, format!("\"{}\" is not a valid {}", value, key)))
+}
+
+/// The path options after they have been resolved and checked.
+struct PathConfig<'a> {
+    planner: &'a Planner,
+    smoothing: Smoothing,
+    spacing: Option<f64>,
+    resolution: Option<f64>,
+    max_grid_cells: usize,
+    /// Plan to the reachable cell nearest to the goal if the goal itself can't be reached.
+    fallback_to_nearest: bool,
+    budget: Budget,
+}
+
+/// Limits on path requests, read from `Rocket.toml`.
+#[derive(Clone)]
+pub struct PathLimits {
+    /// The largest occupancy grid, in cells, that a single path request may allocate.
+    pub max_grid_cells: usize,
+    /// How long a search may take when the request doesn't say.
+    pub default_timeout: Option<Duration>,
+    /// How long a search may take at most, whatever the request asks for.
+    pub max_timeout: Option<Duration>,
+    /// How many cells a search may expand when the request doesn't say.
+    pub default_expansions: Option<usize>,
+    /// How many cells a search may expand at most, whatever the request asks for.
+    pub max_expansions: Option<usize>,
+}
+
+impl PathLimits {
+    /// The budget of a request before its own `timeout_ms` and `max_expansions` narrow it.
+    fn budget(&self, options: &PathOptions) -> Budget {
+        let timeout = if options.timeout_ms.is_none() { self.default_timeout } else { None };
+        let expansions = if options.max_expansions.is_none() {
+            self.default_expansions
+        } else {
+            None
+        };
+        Budget::default()
+            .limit(self.max_expansions, self.max_timeout)
+            .limit(expansions, timeout)
+    }
+}
+
+/// The body of a path response, which extends `proto::Path` with how the path was found.
+#[derive(Clone, Serialize)]
+pub struct PathResponse {
+    points: Vec<proto::Point>,
+    /// Whether the path stops short of the goal because of the `nearest` fallback.
+    partial: bool,
+    /// How many cells the planner expanded to find the path.
+    expanded: usize,
+    /// Whether an anytime planner ran out of budget before it could find the shortest path.
+    suboptimal: bool,
+}
 
-#[get("/<problem>/Path")]
-fn get(state: State<super::State>, problem: &str)
-        -> Result<JSON<proto::Path>, status::Custom<()>> {
-    // Attempt to access the problem.
-    if let Some(problem) = state.lock().unwrap().get(problem) {
-        get_path(problem).map(JSON).map_err(|_| status::Custom(Status::BadRequest, ()))
+/// The reasons a path request can fail.
+#[derive(Debug, PartialEq)]
+enum PathError {
+    MissingRobot,
+    MissingGoal,
+    MissingBoundary,
+    GridTooLarge { resolution: f64, cells: usize, max_cells: usize },
+    /// The spacing asked for is finer than the grid cells.
+    SpacingTooFine { spacing: f64, resolution: f64 },
+    /// The robot or goal, named by the field, lies outside the boundary.
+    OutsideBoundary(&'static str),
+    NoPath(Diagnosis),
+    /// The search ran out of budget before it could finish.
+    Stopped(Stop),
+}
+
+impl From<PathError> for Error {
+    fn from(error: PathError) -> Error {
+        match error {
+            PathError::MissingRobot => Error::missing("robot"),
+            PathError::MissingGoal => Error::missing("goal"),
+            PathError::MissingBoundary => Error::missing("boundary"),
+            PathError::GridTooLarge { resolution, cells, max_cells } => {
+                Error::new(Status::BadRequest,
+                           "grid_too_large",
+                           format!("a resolution of {} needs {} grid cells, but at most {} are \
+                                    allowed", resolution, cells, max_cells))
+                    .with_field("resolution")
+            }
+            PathError::SpacingTooFine { spacing, resolution } => {
+                Error::invalid("spacing",
+                               format!("a spacing of {} is finer than the grid resolution of {}",
+                                       spacing, resolution))
+            }
+            PathError::OutsideBoundary(field) => {
+                Error::new(Status::UnprocessableEntity,
+                           "outside_boundary",
+                           format!("the {} is outside the boundary", field))
+                    .with_field(field)
+            }
+            PathError::NoPath(diagnosis) => {
+                let message = match diagnosis.reason {
+                    "start_blocked" => "the robot is inside an obstacle",
+                    "goal_blocked" => "the goal is inside an obstacle",
+                    _ => "obstacles separate the robot from the goal",
+                };
+                Error::new(Status::BadRequest, "no_path", message).with_details(diagnosis)
+            }
+            PathError::Stopped(Stop::Cancelled) => {
+                Error::new(Status::Conflict, "cancelled", "the search was cancelled")
+            }
+            PathError::Stopped(stop) => {
+                Error::new(Status::BadRequest,
+                           "budget_exceeded",
+                           "the search ran out of budget before finding a path")
+                    .with_details(stop)
+            }
+        }
+    }
+}
+
+#[get("/<problem>/Path?<options>", rank = 1)]
+fn get(state: State<super::State>,
+       limits: State<PathLimits>,
+       problem: &str,
+       options: PathOptions) -> Result<Tagged<JSON<PathResponse>>, Error> {
+    respond(&state, &limits, problem, options)
+}
+
+#[get("/<problem>/Path", rank = 2)]
+fn get_default(state: State<super::State>,
+               limits: State<PathLimits>,
+               problem: &str) -> Result<Tagged<JSON<PathResponse>>, Error> {
+    respond(&state, &limits, problem, PathOptions::default())
+}
+
+fn respond(state: &super::State, limits: &PathLimits, name: &str, options: PathOptions)
+        -> Result<Tagged<JSON<PathResponse>>, Error> {
+    // Plan on a copy so the problem stays available to other requests in the meantime. A
+    // D* Lite search is taken along rather than copied, so it can be repaired and put back,
+    // and other requests for it wait until then instead of starting over.
+    let repairs = options.algorithm.as_ref().map_or(false, |algorithm| algorithm == "dstar");
+    if repairs {
+        state.one_at_a_time(name, || plan_on_copy(state, limits, name, options, true))
     } else {
-        Err(status::Custom(Status::NotFound, ()))
+        plan_on_copy(state, limits, name, options, false)
     }
 }
 
-fn get_path(problem: &Problem) -> Result<proto::Path, ()> {
-    use ndarray::Array2;
-    use std::convert::TryFrom;
-    use pathfinding;
-    let robot = problem.robot.as_ref().ok_or(())?;
-    let goal = problem.goal.as_ref().ok_or(())?;
-    let boundary = problem.boundary.as_ref().ok_or(())?;
+fn plan_on_copy(state: &super::State,
+                limits: &PathLimits,
+                name: &str,
+                options: PathOptions,
+                repairs: bool) -> Result<Tagged<JSON<PathResponse>>, Error> {
+    let mut problem = state.checkout(name, repairs)?;
+    let budget = limits.budget(&options);
+    let result = search(&problem, &options, limits.max_grid_cells, budget);
 
-    let grid_granularity = robot.radius / 4.0;
-    let grid_height = (boundary.length / grid_granularity) as usize;
-    let grid_width = (boundary.width / grid_granularity) as usize;
-    let mut grid = Array2::from_elem((grid_width, grid_height), false);
+    // Keep the grid and search for later requests, unless the problem changed in the
+    // meantime, or was replaced by another one under the same name.
+    let version = problem.version();
+    let built = result.as_ref().ok().and_then(|&(_, ref built)| built.clone());
+    let repaired = if repairs { Some(problem.dstar.take()) } else { None };
+    if built.is_some() || repaired.is_some() {
+        state.keep(name, version, built, repaired);
+    }
+    let (response, _) = result?;
+    Ok(Tagged(JSON(response), version))
+}
 
-    let to_coords = move |pos: [f64; 2]| -> [usize; 2] {
-        [((pos[0] - boundary.point.x) / grid_granularity + 0.5) as usize,
-            ((pos[1] - boundary.point.y) / grid_granularity + 0.5) as usize]
-    };
+/// Checks the options of a path request without running it.
+pub fn check(problem: &Problem, options: &PathOptions, max_grid_cells: usize)
+        -> Result<(), Error> {
+    if let Some(ref error) = options.invalid {
+        return Err(error.clone());
+    }
+    let planner = planner_for(problem, options)?;
+    configure(problem, &*planner, options, max_grid_cells, Budget::default()).map(|_| ())
+}
+
+/// Runs a path request on a problem within a budget.
+///
+/// The grid is returned as well when it had to be built, so the caller can cache it.
+pub fn search(problem: &Problem, options: &PathOptions, max_grid_cells: usize, budget: Budget)
+        -> Result<(PathResponse, Option<Arc<Grid>>), Error> {
+    if let Some(ref error) = options.invalid {
+        return Err(error.clone());
+    }
+    let planner = planner_for(problem, options)?;
+    let timeout = options.timeout_ms.map(Duration::from_millis);
+    let budget = budget.limit(options.max_expansions, timeout);
+    let config = configure(problem, &*planner, options, max_grid_cells, budget)?;
+    let (grid, built) = grid_for(problem, &config)?;
+    let response = plan(problem, &grid, &config)?;
+    Ok((response, if built { Some(grid) } else { None }))
+}
 
-    let from_coords = move |coord: [usize; 2]| -> [f64; 2] {
-        [coord[0] as f64 * grid_granularity + boundary.point.x,
-            coord[1] as f64 * grid_granularity + boundary.point.y]
+/// The planner a request asks for, which may be the problem's own D* Lite search.
+fn planner_for<'a>(problem: &'a Problem, options: &PathOptions)
+        -> Result<Box<Planner + 'a>, Error> {
+    let algorithm = options.algorithm.as_ref().map(|s| s.as_str()).unwrap_or(planner::DEFAULT);
+    match algorithm {
+        "dstar" => Ok(Box::new(&problem.dstar) as Box<Planner + 'a>),
+        _ => {
+            planner::by_name(algorithm)
+                .map(|planner| planner as Box<Planner + 'a>)
+                .ok_or_else(|| Error::invalid("algorithm",
+                                              format!("unknown algorithm \"{}\"", algorithm)))
+        }
+    }
+}
+
+/// Checks the rest of the options and resolves them against the problem's settings.
+fn configure<'a>(problem: &Problem,
+                 planner: &'a Planner,
+                 options: &PathOptions,
+                 max_grid_cells: usize,
+                 budget: Budget) -> Result<PathConfig<'a>, Error> {
+    let smoothing = match options.smooth {
+        Some(ref smooth) => {
+            Smoothing::by_name(smooth)
+                .ok_or_else(|| Error::invalid("smooth",
+                                              format!("unknown smoothing \"{}\"", smooth)))?
+        }
+        None => Smoothing::None,
     };
+    // Distances have to be positive.
+    let positive = |d: Option<f64>| d.map_or(true, |d| d > 0.0 && d.is_finite());
+    if !positive(options.spacing) {
+        return Err(Error::invalid("spacing", "the spacing must be a positive distance"));
+    }
+    if !positive(options.resolution) {
+        return Err(Error::invalid("resolution", "the resolution must be a positive distance"));
+    }
+    if options.timeout_ms == Some(0) {
+        return Err(Error::invalid("timeout_ms", "the timeout must be at least a millisecond"));
+    }
+    if options.max_expansions == Some(0) {
+        return Err(Error::invalid("max_expansions", "at least one expansion must be allowed"));
+    }
+    let fallback_to_nearest = match options.fallback.as_ref().map(|s| s.as_str()) {
+        None | Some("none") => false,
+        Some("nearest") => true,
+        Some(fallback) => {
+            return Err(Error::invalid("fallback",
+                                      format!("unknown fallback \"{}\"", fallback)));
+        }
+    };
+
+    Ok(PathConfig {
+        planner: planner,
+        smoothing: smoothing,
+        spacing: options.spacing,
+        resolution: options.resolution.or(problem.planner.resolution),
+        max_grid_cells: max_grid_cells,
+        fallback_to_nearest: fallback_to_nearest,
+        budget: budget,
+    })
+}
 
-    for obstacle in problem.obstacles.values() {
-        let start = to_coords(
-            [obstacle.point.x - robot.radius, obstacle.point.y - robot.radius]
-        );
-        let end = to_coords(
-            [obstacle.point.x + obstacle.width + robot.radius,
-                obstacle.point.y + obstacle.length + robot.radius]
-        );
-        for (x, y) in (start[0]..end[0] + 1).cartesian_product((start[1]..end[1] + 1)) {
-            grid[[x, y]] = true;
+/// The occupancy grid for a request, and whether it had to be built instead of reusing the
+/// problem's cached one.
+fn grid_for(problem: &Problem, config: &PathConfig) -> Result<(Arc<Grid>, bool), PathError> {
+    let robot = problem.robot.as_ref().ok_or(PathError::MissingRobot)?;
+    problem.goal.as_ref().ok_or(PathError::MissingGoal)?;
+    let boundary = problem.boundary.as_ref().ok_or(PathError::MissingBoundary)?;
+
+    // Refuse to allocate grids beyond the server limit.
+    let granularity = config.resolution.unwrap_or_else(|| grid::default_granularity(robot));
+    let cells = grid::cell_count(boundary, granularity);
+    if cells > config.max_grid_cells {
+        return Err(PathError::GridTooLarge {
+            resolution: granularity,
+            cells: cells,
+            max_cells: config.max_grid_cells,
+        });
+    }
+    // Points closer together than the grid cells add nothing but size to the response.
+    if let Some(spacing) = config.spacing {
+        if spacing < granularity {
+            return Err(PathError::SpacingTooFine { spacing: spacing, resolution: granularity });
         }
     }
+    if let Some(ref grid) = problem.grid {
+        if grid.fits(boundary, robot, granularity) {
+            return Ok((grid.clone(), false));
+        }
+    }
+    Ok((Arc::new(Grid::new(boundary, robot, granularity, problem.obstacles.values())), true))
+}
 
-    let checked_coord_add = |coord: [usize; 2], add: [isize; 2]| -> Option<[usize; 2]> {
-        let new_coord = [coord[0] as isize + add[0], coord[1] as isize + add[1]];
-        if let (Ok(x), Ok(y)) = (usize::try_from(new_coord[0]), usize::try_from(new_coord[1])) {
-            if x < grid_width && y < grid_height {
-                Some([x, y])
+fn plan(problem: &Problem, grid: &Grid, config: &PathConfig) -> Result<PathResponse, PathError> {
+    let robot = problem.robot.as_ref().ok_or(PathError::MissingRobot)?;
+    let goal = problem.goal.as_ref().ok_or(PathError::MissingGoal)?;
+
+    let start = [robot.point.x, robot.point.y];
+    let end = [goal.point.x, goal.point.y];
+    let start_cell = grid.to_coords(start).ok_or(PathError::OutsideBoundary("robot"))?;
+    let end_cell = grid.to_coords(end).ok_or(PathError::OutsideBoundary("goal"))?;
+    let mut partial = false;
+    let expansions = Expansions::new(config.budget.clone());
+    let cells = match config.planner.plan(grid, start_cell, end_cell, &expansions) {
+        Some(cells) => cells,
+        None => {
+            if let Some(stop) = expansions.stopped() {
+                return Err(PathError::Stopped(stop));
+            }
+            // Head for whatever free cell the robot can reach that is closest to the goal.
+            let nearest = if config.fallback_to_nearest {
+                grid.nearest_reachable(start_cell, end, &expansions)
             } else {
                 None
+            };
+            let retry = |nearest| config.planner.plan(grid, start_cell, nearest, &expansions);
+            match nearest.and_then(retry) {
+                Some(cells) => {
+                    partial = true;
+                    cells
+                }
+                None => {
+                    if let Some(stop) = expansions.stopped() {
+                        return Err(PathError::Stopped(stop));
+                    }
+                    let diagnosis = diagnose::diagnose(grid,
+                                                       &problem.obstacles,
+                                                       start_cell,
+                                                       end_cell,
+                                                       &expansions);
+                    return Err(match diagnosis {
+                        Some(diagnosis) => PathError::NoPath(diagnosis),
+                        None => PathError::Stopped(expansions.stopped().unwrap_or(Stop::Timeout)),
+                    });
+                }
             }
-        } else {
-            None
         }
     };
 
-    pathfinding::bfs(
-        &to_coords([robot.point.x, robot.point.y]),
-        |coord| {
-            vec![[-1, -1], [-1, 0], [-1, 1], [0, -1], [0, 1], [1, -1], [1, 0], [1, 1]]
-                .into_iter()
-                .map(|c| checked_coord_add(*coord, c))
-                .flatten()
-                .filter_map(|c| if grid[c] {None} else {Some(c)})
-                .collect_vec()
-                .into_iter()
-        },
-        |coord| *coord == to_coords([goal.point.x, goal.point.y])
-    ).map(|v| proto::Path{ points: v.into_iter().map(from_coords)
-        .map(|c| proto::Point{ x: c[0], y: c[1] }).collect()})
-        .ok_or(())
+    let spacing = config.spacing.unwrap_or(grid.granularity());
+    Ok(PathResponse {
+        points: config.smoothing.apply(grid, &cells, spacing).into_iter()
+            .map(|c| proto::Point{ x: c[0], y: c[1] })
+            .collect(),
+        partial: partial,
+        expanded: expansions.total(),
+        suboptimal: expansions.is_suboptimal(),
+    })
 }
 
 #[cfg(test)]
@@ -123,6 +461,14 @@ mod test {
         let response = request.dispatch_with(&rocket);
         assert_eq!(response.status(), Status::Ok);
 
+        // The path can't be found without a robot.
+        let mut request = MockRequest::new(Method::Get, "/test/Path");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "missing");
+        assert_eq!(body["field"], "robot");
+
         // Post robot to "test".
         let mut request = MockRequest::new(Method::Post, "/test/Robot")
             .header(ContentType::JSON)
@@ -147,5 +493,389 @@ mod test {
         let mut response = request.dispatch_with(&rocket);
         assert_eq!(response.status(), Status::Ok);
         assert!(body_deser::<proto::Path>(&mut response).is_some());
+
+        // Access the path with every planner.
+        for algorithm in &["bfs", "dijkstra", "astar", "jps", "theta", "dstar", "ara"] {
+            let mut request = MockRequest::new(Method::Get,
+                                               format!("/test/Path?algorithm={}", algorithm));
+            let mut response = request.dispatch_with(&rocket);
+            assert_eq!(response.status(), Status::Ok);
+            assert!(body_deser::<proto::Path>(&mut response).is_some());
+        }
+
+        // Moving the robot lets D* Lite repair its last search rather than start over.
+        let mut request = MockRequest::new(Method::Get, "/test/Path?algorithm=astar");
+        let mut response = request.dispatch_with(&rocket);
+        let fresh = body_deser::<serde_json::Value>(&mut response).unwrap();
+        let mut request = MockRequest::new(Method::Put, "/test/Robot")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&proto::Robot{
+                point: proto::Point{ x: -0.9, y: -0.9 },
+                radius: 0.2,
+            }).unwrap());
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Path?algorithm=dstar");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let repaired = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert!(repaired["expanded"].as_u64().unwrap() < fresh["expanded"].as_u64().unwrap());
+
+        // Searches that run out of budget fail cleanly.
+        let mut request = MockRequest::new(Method::Get,
+                                           "/test/Path?algorithm=astar&max_expansions=1");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "budget_exceeded");
+        assert_eq!(body["details"], "max_expansions");
+        let mut request = MockRequest::new(Method::Get, "/test/Path?timeout_ms=0");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["field"], "timeout_ms");
+        // Budgets that don't parse, or are misspelled, aren't dropped in favour of the default.
+        for &(query, field) in &[("timeout_ms=soon", "timeout_ms"),
+                                 ("max_expansions=-1", "max_expansions"),
+                                 ("max_expansion=10", "max_expansion")] {
+            let mut request = MockRequest::new(Method::Get, format!("/test/Path?{}", query));
+            let mut response = request.dispatch_with(&rocket);
+            assert_eq!(response.status(), Status::BadRequest);
+            let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+            assert_eq!(body["code"], "invalid");
+            assert_eq!(body["field"], field);
+        }
+        let mut request = MockRequest::new(Method::Get, "/test/Path?algorithm=ara&timeout_ms=1000");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["suboptimal"], false);
+        let mut request = MockRequest::new(Method::Get,
+                                           "/test/Path?timeout_ms=18446744073709551615");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Smooth the path.
+        for smooth in &["none", "shortcut", "spline"] {
+            let mut request = MockRequest::new(Method::Get,
+                                               format!("/test/Path?smooth={}", smooth));
+            let mut response = request.dispatch_with(&rocket);
+            assert_eq!(response.status(), Status::Ok);
+            assert!(body_deser::<proto::Path>(&mut response).is_some());
+        }
+
+        // Ask for a smoothing stage that doesn't exist.
+        let mut request = MockRequest::new(Method::Get, "/test/Path?smooth=wobbly");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Use a finer grid than the default.
+        let mut request = MockRequest::new(Method::Get, "/test/Path?resolution=0.025");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert!(body_deser::<proto::Path>(&mut response).is_some());
+
+        // Ask for a grid far larger than the server allows.
+        let mut request = MockRequest::new(Method::Get, "/test/Path?resolution=0.00001");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "grid_too_large");
+
+        // Ask for a planner that doesn't exist.
+        let mut request = MockRequest::new(Method::Get, "/test/Path?algorithm=teleport");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Bury the goal in an obstacle.
+        let mut request = MockRequest::new(Method::Post, "/test/Obstacles/crate")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&proto::Obstacle{
+                width: 1.0,
+                length: 1.0,
+                point: proto::Point{ x: 1.5, y: 1.5 },
+            }).unwrap());
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // The error explains which obstacle is in the way.
+        let mut request = MockRequest::new(Method::Get, "/test/Path");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "no_path");
+        assert_eq!(body["details"]["reason"], "goal_blocked");
+        assert_eq!(body["details"]["obstacles"][0], "crate");
+
+        // Fall back to getting as close to the goal as possible.
+        let mut request = MockRequest::new(Method::Get, "/test/Path?fallback=nearest");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["partial"], true);
+        assert!(body["points"].as_array().map_or(false, |points| !points.is_empty()));
+
+        // Add an obstacle that sticks out past the corner of the boundary.
+        let mut request = MockRequest::new(Method::Post, "/test/Obstacles/overhang")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&proto::Obstacle{
+                width: 3.0,
+                length: 3.0,
+                point: proto::Point{ x: 4.0, y: -6.0 },
+            }).unwrap());
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // It is clipped to the grid rather than breaking planning.
+        let mut request = MockRequest::new(Method::Get, "/test/Path?fallback=nearest");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Moving the robot outside the boundary is rejected.
+        let mut request = MockRequest::new(Method::Put, "/test/Robot")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&proto::Robot{
+                point: proto::Point{ x: 20.0, y: 20.0 },
+                radius: 0.2,
+            }).unwrap());
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["code"], "invalid_payload");
+        assert_eq!(body["field"], "point");
+    }
+
+    /// Plans a path the way `respond` does, on the grid `grid_for` gives.
+    fn get_path(problem: &super::Problem, config: &super::PathConfig)
+            -> Result<super::PathResponse, super::PathError> {
+        let (grid, _) = super::grid_for(problem, config)?;
+        super::plan(problem, &grid, config)
+    }
+
+    fn config(planner: &super::Planner) -> super::PathConfig {
+        super::PathConfig {
+            planner: planner,
+            smoothing: super::Smoothing::None,
+            spacing: None,
+            resolution: None,
+            max_grid_cells: super::super::DEFAULT_MAX_GRID_CELLS,
+            fallback_to_nearest: false,
+            budget: Default::default(),
+        }
+    }
+
+    #[test]
+    fn euclidean() {
+        let mut problem = super::super::Problem::default();
+        problem.boundary = Some(proto::Boundary{
+            width: 10.0,
+            length: 10.0,
+            point: proto::Point{ x: -5.0, y: -5.0 },
+        });
+        problem.robot = Some(proto::Robot{
+            point: proto::Point{ x: -4.0, y: -4.0 },
+            radius: 0.4,
+        });
+        problem.goal = Some(proto::Goal{
+            point: proto::Point{ x: 4.0, y: -2.0 },
+        });
+
+        // With no obstacles the path must be as long as the octile distance, not a zig-zag.
+        let path = get_path(&problem, &config(&super::planner::AStar)).unwrap();
+        let length: f64 = path.points.windows(2)
+            .map(|w| ((w[1].x - w[0].x).powi(2) + (w[1].y - w[0].y).powi(2)).sqrt())
+            .sum();
+        let optimal = 2.0 * 2.0f64.sqrt() + 6.0;
+        assert!((length - optimal).abs() < 1e-6, "path length {} is not {}", length, optimal);
+
+        // An any-angle path across open space is a single segment.
+        let path = get_path(&problem, &config(&super::planner::ThetaStar)).unwrap();
+        assert_eq!(path.points.len(), 2);
+
+        // A goal outside the boundary is rejected rather than indexing off the grid.
+        problem.goal = Some(proto::Goal{
+            point: proto::Point{ x: 40.0, y: -2.0 },
+        });
+        assert_eq!(get_path(&problem, &config(&super::planner::AStar)).err(),
+                   Some(super::PathError::OutsideBoundary("goal")));
+    }
+
+    #[test]
+    fn smoothing() {
+        use grid::{self, Grid};
+
+        let mut problem = super::super::Problem::default();
+        problem.boundary = Some(proto::Boundary{
+            width: 10.0,
+            length: 10.0,
+            point: proto::Point{ x: -5.0, y: -5.0 },
+        });
+        problem.robot = Some(proto::Robot{
+            point: proto::Point{ x: -4.0, y: 0.0 },
+            radius: 0.4,
+        });
+        problem.goal = Some(proto::Goal{
+            point: proto::Point{ x: 4.0, y: 0.0 },
+        });
+        // A wall between the robot and the goal forces the path around it.
+        problem.obstacles.insert(String::from("wall"), proto::Obstacle{
+            width: 1.0,
+            length: 6.0,
+            point: proto::Point{ x: -0.5, y: -3.0 },
+        });
+        let grid = Grid::new(problem.boundary.as_ref().unwrap(),
+                             problem.robot.as_ref().unwrap(),
+                             0.1,
+                             problem.obstacles.values());
+
+        let raw = get_path(&problem, &config(&super::planner::AStar)).unwrap();
+
+        // Shortcutting keeps only waypoints that can see each other.
+        let mut shortcut = config(&super::planner::AStar);
+        shortcut.smoothing = super::Smoothing::Shortcut;
+        let path = get_path(&problem, &shortcut).unwrap();
+        assert!(path.points.len() < raw.points.len());
+        for pair in path.points.windows(2) {
+            let a = grid.to_coords([pair[0].x, pair[0].y]).unwrap();
+            let b = grid.to_coords([pair[1].x, pair[1].y]).unwrap();
+            assert!(grid.line_of_sight(a, b));
+        }
+
+        // Every point of the spline must be free.
+        let mut spline = config(&super::planner::AStar);
+        spline.smoothing = super::Smoothing::Spline;
+        spline.spacing = Some(0.1);
+        let path = get_path(&problem, &spline).unwrap();
+        for point in &path.points {
+            assert!(grid.is_free(grid.to_coords([point.x, point.y]).unwrap()));
+        }
+
+        // A spacing finer than the grid is refused rather than giving more points than the grid
+        // has cells.
+        spline.spacing = Some(1e-12);
+        assert_eq!(get_path(&problem, &spline).err(),
+                   Some(super::PathError::SpacingTooFine { spacing: 1e-12, resolution: 0.1 }));
+    }
+
+    #[test]
+    fn cache() {
+        let mut problem = super::super::Problem::default();
+        problem.boundary = Some(proto::Boundary{
+            width: 10.0,
+            length: 10.0,
+            point: proto::Point{ x: 0.0, y: 0.0 },
+        });
+        problem.robot = Some(proto::Robot{
+            point: proto::Point{ x: 1.0, y: 1.0 },
+            radius: 0.4,
+        });
+        problem.goal = Some(proto::Goal{
+            point: proto::Point{ x: 9.0, y: 9.0 },
+        });
+        // Two obstacles overlapping around (5, 5).
+        problem.insert_obstacle(String::from("a"), proto::Obstacle{
+            width: 2.0,
+            length: 2.0,
+            point: proto::Point{ x: 4.0, y: 4.0 },
+        });
+        problem.insert_obstacle(String::from("b"), proto::Obstacle{
+            width: 2.0,
+            length: 2.0,
+            point: proto::Point{ x: 5.0, y: 5.0 },
+        });
+
+        // The first request builds the grid and later ones reuse it.
+        let astar = config(&super::planner::AStar);
+        let (grid, built) = super::grid_for(&problem, &astar).unwrap();
+        assert!(built);
+        problem.grid = Some(grid);
+        let (grid, built) = super::grid_for(&problem, &astar).unwrap();
+        assert!(!built);
+        let overlap = grid.to_coords([5.5, 5.5]).unwrap();
+        assert!(!grid.is_free(overlap));
+        drop(grid);
+
+        // Removing one obstacle leaves the cells the other still covers occupied.
+        problem.remove_obstacle("a");
+        let (grid, _) = super::grid_for(&problem, &astar).unwrap();
+        assert!(!grid.is_free(overlap));
+        assert!(grid.is_free(grid.to_coords([4.2, 4.2]).unwrap()));
+        drop(grid);
+        problem.remove_obstacle("b");
+        let (grid, built) = super::grid_for(&problem, &astar).unwrap();
+        assert!(!built);
+        assert!(grid.is_free(overlap));
+
+        // A bigger robot needs a new grid.
+        problem.robot.as_mut().unwrap().radius = 0.8;
+        let (_, built) = super::grid_for(&problem, &astar).unwrap();
+        assert!(built);
+    }
+
+    #[test]
+    fn budget() {
+        use planner::Budget;
+
+        let mut problem = super::super::Problem::default();
+        problem.boundary = Some(proto::Boundary{
+            width: 10.0,
+            length: 10.0,
+            point: proto::Point{ x: 0.0, y: 0.0 },
+        });
+        problem.robot = Some(proto::Robot{
+            point: proto::Point{ x: 1.0, y: 5.0 },
+            radius: 0.4,
+        });
+        problem.goal = Some(proto::Goal{
+            point: proto::Point{ x: 9.0, y: 5.0 },
+        });
+        // A cup opening away from the robot traps the inflated first pass of ARA*.
+        problem.insert_obstacle(String::from("back"), proto::Obstacle{
+            width: 0.5,
+            length: 6.0,
+            point: proto::Point{ x: 6.0, y: 2.0 },
+        });
+        problem.insert_obstacle(String::from("top"), proto::Obstacle{
+            width: 3.0,
+            length: 0.5,
+            point: proto::Point{ x: 3.0, y: 7.5 },
+        });
+        problem.insert_obstacle(String::from("bottom"), proto::Obstacle{
+            width: 3.0,
+            length: 0.5,
+            point: proto::Point{ x: 3.0, y: 2.0 },
+        });
+        let length = |path: &super::PathResponse| -> f64 {
+            path.points.windows(2)
+                .map(|w| ((w[1].x - w[0].x).powi(2) + (w[1].y - w[0].y).powi(2)).sqrt())
+                .sum()
+        };
+
+        // Given enough room ARA* finds the same length of path as A*.
+        let optimal = get_path(&problem, &config(&super::planner::AStar)).unwrap();
+        let full = get_path(&problem, &config(&super::planner::AraStar)).unwrap();
+        assert!(!full.suboptimal);
+        assert!((length(&full) - length(&optimal)).abs() < 1e-6);
+
+        // Cut short, it settles for the best path it found so far.
+        let mut limited = config(&super::planner::AraStar);
+        limited.budget = Budget { max_expansions: Some(full.expanded - 1), ..Budget::default() };
+        let path = get_path(&problem, &limited).unwrap();
+        assert!(path.suboptimal);
+        assert!(length(&path) >= length(&optimal) - 1e-6);
+
+        // Planners that aren't anytime just stop.
+        let mut limited = config(&super::planner::AStar);
+        limited.budget = Budget { max_expansions: Some(10), ..Budget::default() };
+        assert_eq!(get_path(&problem, &limited).err(),
+                   Some(super::PathError::Stopped(super::Stop::MaxExpansions)));
+
+        // Jump point search counts every cell it jumps over, not just the jump points.
+        let jps = get_path(&problem, &config(&super::planner::Jps)).unwrap();
+        assert!(jps.expanded >= jps.points.len() - 1);
+        let mut limited = config(&super::planner::Jps);
+        limited.budget = Budget { max_expansions: Some(10), ..Budget::default() };
+        assert_eq!(get_path(&problem, &limited).err(),
+                   Some(super::PathError::Stopped(super::Stop::MaxExpansions)));
     }
 }
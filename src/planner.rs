@@ -0,0 +1,564 @@
+use std::cell;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::cmp;
+use std::time::{Duration, Instant};
+use pathfinding;
+
+use grid::{self, Cell, Grid};
+
+/// The algorithm used when a path request doesn't ask for one.
+pub const DEFAULT: &'static str = "astar";
+
+/// A search strategy that finds a route through an occupancy grid.
+pub trait Planner {
+    /// Finds a sequence of waypoint cells leading from `start` to `goal`, both included.
+    ///
+    /// Grid planners return adjacent cells, while any-angle planners only return the corners,
+    /// in which case every waypoint is in line of sight of the next. Every cell the search
+    /// expands is counted in `expansions`, and the search gives up with `None` once they say so.
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>>;
+}
+
+/// A borrowed planner plans the same, for planners that live in the problem they plan for.
+impl<'a, P: Planner + ?Sized> Planner for &'a P {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        (**self).plan(grid, start, goal, expansions)
+    }
+}
+
+/// Limits on how much work a single search may do.
+#[derive(Clone, Default)]
+pub struct Budget {
+    pub max_expansions: Option<usize>,
+    pub deadline: Option<Instant>,
+    /// Set from elsewhere to stop the search early.
+    pub cancelled: Option<Arc<AtomicBool>>,
+}
+
+/// The longest timeout a search honours, in seconds, which keeps deadlines from overflowing.
+const MAX_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+/// The moment `timeout` from now, or a day from now for longer timeouts.
+pub fn deadline(timeout: Duration) -> Instant {
+    Instant::now() + cmp::min(timeout, Duration::from_secs(MAX_TIMEOUT_SECS))
+}
+
+impl Budget {
+    /// Narrows the budget down to at most `max_expansions`, ending `timeout` from now.
+    pub fn limit(mut self, max_expansions: Option<usize>, timeout: Option<Duration>) -> Budget {
+        if let Some(max) = max_expansions {
+            self.max_expansions = Some(self.max_expansions.map_or(max, |m| cmp::min(m, max)));
+        }
+        if let Some(timeout) = timeout {
+            let deadline = deadline(timeout);
+            self.deadline = Some(self.deadline.map_or(deadline, |d| cmp::min(d, deadline)));
+        }
+        self
+    }
+}
+
+/// Why a search gave up before finishing.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Stop {
+    #[serde(rename = "cancelled")]
+    Cancelled,
+    #[serde(rename = "timeout")]
+    Timeout,
+    #[serde(rename = "max_expansions")]
+    MaxExpansions,
+}
+
+/// How often the clock and cancellation flag are checked, in expansions.
+const CHECK_INTERVAL: usize = 256;
+
+/// The number of cells a planner expanded, which also enforces the budget of the search.
+#[derive(Default)]
+pub struct Expansions {
+    total: cell::Cell<usize>,
+    budget: Budget,
+    stopped: cell::Cell<Option<Stop>>,
+    suboptimal: cell::Cell<bool>,
+}
+
+impl Expansions {
+    pub fn new(budget: Budget) -> Expansions {
+        Expansions { budget: budget, ..Expansions::default() }
+    }
+
+    /// Counts an expansion, returning `false` once the search has to stop.
+    pub fn count(&self) -> bool {
+        if self.stopped.get().is_some() {
+            return false;
+        }
+        let total = self.total.get() + 1;
+        self.total.set(total);
+        let stop = if self.budget.max_expansions.map_or(false, |max| total > max) {
+            Some(Stop::MaxExpansions)
+        } else if total % CHECK_INTERVAL != 0 {
+            None
+        } else if self.budget.cancelled.as_ref().map_or(false, |c| c.load(AtomicOrdering::SeqCst)) {
+            Some(Stop::Cancelled)
+        } else if self.budget.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            Some(Stop::Timeout)
+        } else {
+            None
+        };
+        self.stopped.set(stop);
+        stop.is_none()
+    }
+
+    pub fn total(&self) -> usize {
+        self.total.get()
+    }
+
+    /// Why the search had to stop, if it did.
+    pub fn stopped(&self) -> Option<Stop> {
+        self.stopped.get()
+    }
+
+    /// Records that the path found is the best so far rather than the shortest.
+    pub fn mark_suboptimal(&self) {
+        self.suboptimal.set(true);
+    }
+
+    pub fn is_suboptimal(&self) -> bool {
+        self.suboptimal.get()
+    }
+}
+
+/// Looks up a planner by the name used in the `algorithm` query parameter.
+pub fn by_name(name: &str) -> Option<Box<Planner>> {
+    match name {
+        "bfs" => Some(Box::new(Bfs)),
+        "dijkstra" => Some(Box::new(Dijkstra)),
+        "astar" => Some(Box::new(AStar)),
+        "jps" => Some(Box::new(Jps)),
+        "theta" => Some(Box::new(ThetaStar)),
+        "ara" => Some(Box::new(AraStar)),
+        _ => None,
+    }
+}
+
+/// Breadth-first search, which minimizes the number of steps rather than the distance.
+pub struct Bfs;
+
+impl Planner for Bfs {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        pathfinding::bfs(
+            &start,
+            |&coord| {
+                // Running out of budget leaves nothing to expand, which ends the search.
+                let successors = if expansions.count() { grid.successors(coord) } else { vec![] };
+                successors.into_iter().map(|(n, _)| n)
+            },
+            |&coord| coord == goal
+        )
+    }
+}
+
+/// Dijkstra's algorithm with Euclidean step costs.
+pub struct Dijkstra;
+
+impl Planner for Dijkstra {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        pathfinding::dijkstra(
+            &start,
+            |&coord| if expansions.count() { grid.successors(coord) } else { vec![] },
+            |&coord| coord == goal
+        ).map(|(path, _)| path)
+    }
+}
+
+/// A* with Euclidean step costs and the octile distance as its heuristic.
+pub struct AStar;
+
+impl Planner for AStar {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        pathfinding::astar(
+            &start,
+            |&coord| if expansions.count() { grid.successors(coord) } else { vec![] },
+            |&coord| grid::octile_distance(coord, goal),
+            |&coord| coord == goal
+        ).map(|(path, _)| path)
+    }
+}
+
+/// Jump point search, which finds the same paths as A* while skipping over open areas.
+///
+/// Diagonal moves are allowed whenever the destination is free, matching `Grid::successors`.
+pub struct Jps;
+
+impl Planner for Jps {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        if !grid.is_free(start) || !grid.is_free(goal) {
+            return None;
+        }
+
+        let mut open = BinaryHeap::new();
+        let mut costs: HashMap<Cell, u64> = HashMap::new();
+        let mut parents: HashMap<Cell, Cell> = HashMap::new();
+        costs.insert(start, 0);
+        open.push(Open { estimate: grid::octile_distance(start, goal), cost: 0, cell: start });
+
+        while let Some(Open { cost, cell, .. }) = open.pop() {
+            if cell == goal {
+                return Some(interpolate(&unwind(&parents, goal)));
+            }
+            // Skip stale heap entries.
+            if costs.get(&cell).map_or(false, |&c| c < cost) {
+                continue;
+            }
+            if !expansions.count() {
+                return None;
+            }
+            for direction in jps_directions(grid, cell, parents.get(&cell).cloned()) {
+                let jump_point = match jump(grid, cell, direction, goal, expansions) {
+                    Some(jump_point) => jump_point,
+                    None if expansions.stopped().is_some() => return None,
+                    None => continue,
+                };
+                let new_cost = cost + grid::octile_distance(cell, jump_point);
+                if costs.get(&jump_point).map_or(true, |&c| new_cost < c) {
+                    costs.insert(jump_point, new_cost);
+                    parents.insert(jump_point, cell);
+                    open.push(Open {
+                        estimate: new_cost + grid::octile_distance(jump_point, goal),
+                        cost: new_cost,
+                        cell: jump_point,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Theta*, an any-angle variant of A* that connects cells to their grandparent when possible.
+///
+/// The result only contains the corners of the path rather than every cell it crosses.
+pub struct ThetaStar;
+
+impl Planner for ThetaStar {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        if !grid.is_free(start) || !grid.is_free(goal) {
+            return None;
+        }
+
+        let mut open = BinaryHeap::new();
+        let mut costs: HashMap<Cell, u64> = HashMap::new();
+        let mut parents: HashMap<Cell, Cell> = HashMap::new();
+        let mut closed = HashSet::new();
+        costs.insert(start, 0);
+        open.push(Open { estimate: grid::euclidean_distance(start, goal), cost: 0, cell: start });
+
+        while let Some(Open { cell, .. }) = open.pop() {
+            if cell == goal {
+                return Some(unwind(&parents, goal));
+            }
+            if !closed.insert(cell) {
+                continue;
+            }
+            if !expansions.count() {
+                return None;
+            }
+            // The start cell is its own parent.
+            let parent = parents.get(&cell).cloned().unwrap_or(cell);
+            for (neighbour, _) in grid.successors(cell) {
+                if closed.contains(&neighbour) {
+                    continue;
+                }
+                // Skip over the current cell entirely if the parent can see the neighbour.
+                let (via, new_cost) = if grid.line_of_sight(parent, neighbour) {
+                    (parent, costs[&parent] + grid::euclidean_distance(parent, neighbour))
+                } else {
+                    (cell, costs[&cell] + grid::euclidean_distance(cell, neighbour))
+                };
+                if costs.get(&neighbour).map_or(true, |&c| new_cost < c) {
+                    costs.insert(neighbour, new_cost);
+                    parents.insert(neighbour, via);
+                    open.push(Open {
+                        estimate: new_cost + grid::euclidean_distance(neighbour, goal),
+                        cost: new_cost,
+                        cell: neighbour,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Heuristic weight of the first ARA* pass, in thousandths.
+const ARA_INITIAL_WEIGHT: u64 = 3000;
+/// How much the weight drops after each ARA* pass, in thousandths.
+const ARA_WEIGHT_STEP: u64 = 500;
+
+/// Anytime Repairing A*, which quickly finds a path with an inflated heuristic and then keeps
+/// lowering the inflation to improve it, reusing the work of earlier passes.
+///
+/// If the budget runs out once a path was found, the best one so far is returned and marked
+/// suboptimal instead of failing.
+pub struct AraStar;
+
+impl Planner for AraStar {
+    fn plan(&self, grid: &Grid, start: Cell, goal: Cell, expansions: &Expansions)
+            -> Option<Vec<Cell>> {
+        if !grid.is_free(start) || !grid.is_free(goal) {
+            return None;
+        }
+
+        let mut ara = Ara {
+            goal: goal,
+            weight: ARA_INITIAL_WEIGHT,
+            open: BinaryHeap::new(),
+            costs: HashMap::new(),
+            parents: HashMap::new(),
+            closed: HashSet::new(),
+            inconsistent: HashSet::new(),
+        };
+        ara.costs.insert(start, 0);
+        ara.push(start);
+
+        let mut best = None;
+        loop {
+            if !ara.improve(grid, expansions) {
+                if best.is_some() {
+                    expansions.mark_suboptimal();
+                }
+                return best;
+            }
+            if !ara.costs.contains_key(&goal) {
+                // Even the inflated search couldn't reach the goal, so nothing will.
+                return None;
+            }
+            best = Some(unwind(&ara.parents, goal));
+            if ara.weight <= 1000 {
+                return best;
+            }
+            ara.weight = cmp::max(ara.weight.saturating_sub(ARA_WEIGHT_STEP), 1000);
+            ara.reopen();
+        }
+    }
+}
+
+/// The state ARA* carries from one pass to the next.
+struct Ara {
+    goal: Cell,
+    /// The current heuristic weight, in thousandths.
+    weight: u64,
+    open: BinaryHeap<Open>,
+    costs: HashMap<Cell, u64>,
+    parents: HashMap<Cell, Cell>,
+    closed: HashSet<Cell>,
+    /// Cells whose cost dropped after they were expanded in the current pass.
+    inconsistent: HashSet<Cell>,
+}
+
+impl Ara {
+    fn push(&mut self, cell: Cell) {
+        let cost = self.costs[&cell];
+        let heuristic = grid::octile_distance(cell, self.goal).saturating_mul(self.weight) / 1000;
+        self.open.push(Open { estimate: cost.saturating_add(heuristic), cost: cost, cell: cell });
+    }
+
+    /// Expands cells until the path to the goal can't be improved at the current weight.
+    ///
+    /// Returns `false` if the budget ran out first.
+    fn improve(&mut self, grid: &Grid, expansions: &Expansions) -> bool {
+        loop {
+            let (estimate, cost, cell) = match self.open.peek() {
+                Some(top) => (top.estimate, top.cost, top.cell),
+                None => return true,
+            };
+            // Skip entries that were superseded or already expanded.
+            if self.closed.contains(&cell) || self.costs[&cell] != cost {
+                self.open.pop();
+                continue;
+            }
+            if self.costs.get(&self.goal).map_or(false, |&cost| cost <= estimate) {
+                return true;
+            }
+            if !expansions.count() {
+                return false;
+            }
+            self.open.pop();
+            self.closed.insert(cell);
+            for (neighbour, step) in grid.successors(cell) {
+                let new_cost = cost + step;
+                if self.costs.get(&neighbour).map_or(true, |&c| new_cost < c) {
+                    self.costs.insert(neighbour, new_cost);
+                    self.parents.insert(neighbour, cell);
+                    if self.closed.contains(&neighbour) {
+                        self.inconsistent.insert(neighbour);
+                    } else {
+                        self.push(neighbour);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts the next pass with the open and inconsistent cells, ordered by the new weight.
+    fn reopen(&mut self) {
+        let mut cells: HashSet<Cell> = self.inconsistent.drain().collect();
+        for entry in self.open.drain() {
+            if !self.closed.contains(&entry.cell) {
+                cells.insert(entry.cell);
+            }
+        }
+        self.closed.clear();
+        for cell in cells {
+            self.push(cell);
+        }
+    }
+}
+
+/// The pruned set of directions to jump in from a cell, given the cell it was reached from.
+fn jps_directions(grid: &Grid, cell: Cell, parent: Option<Cell>) -> Vec<[isize; 2]> {
+    let parent = match parent {
+        Some(p) => p,
+        None => return grid::NEIGHBOURS.to_vec(),
+    };
+    let (x, y) = (cell[0] as isize, cell[1] as isize);
+    let dx = (x - parent[0] as isize).signum();
+    let dy = (y - parent[1] as isize).signum();
+    let free = |px: isize, py: isize| grid.is_free_signed([px, py]);
+
+    let mut directions = Vec::new();
+    if dx != 0 && dy != 0 {
+        directions.push([0, dy]);
+        directions.push([dx, 0]);
+        directions.push([dx, dy]);
+        if !free(x - dx, y) {
+            directions.push([-dx, dy]);
+        }
+        if !free(x, y - dy) {
+            directions.push([dx, -dy]);
+        }
+    } else if dx == 0 {
+        directions.push([0, dy]);
+        if !free(x + 1, y) {
+            directions.push([1, dy]);
+        }
+        if !free(x - 1, y) {
+            directions.push([-1, dy]);
+        }
+    } else {
+        directions.push([dx, 0]);
+        if !free(x, y + 1) {
+            directions.push([dx, 1]);
+        }
+        if !free(x, y - 1) {
+            directions.push([dx, -1]);
+        }
+    }
+    directions
+}
+
+/// Moves from `cell` in `direction` until a jump point, the goal, or a blocked cell is reached.
+///
+/// Every cell stepped onto counts as an expansion, and `None` is returned once they run out.
+fn jump(grid: &Grid, cell: Cell, direction: [isize; 2], goal: Cell, expansions: &Expansions)
+        -> Option<Cell> {
+    let (dx, dy) = (direction[0], direction[1]);
+    let free = |px: isize, py: isize| grid.is_free_signed([px, py]);
+    let (mut x, mut y) = (cell[0] as isize, cell[1] as isize);
+    loop {
+        x += dx;
+        y += dy;
+        if !free(x, y) || !expansions.count() {
+            return None;
+        }
+        let current = [x as usize, y as usize];
+        if current == goal {
+            return Some(current);
+        }
+        if dx != 0 && dy != 0 {
+            // A diagonal move stops at forced neighbours or when a straight jump finds something.
+            if (free(x - dx, y + dy) && !free(x - dx, y)) ||
+                (free(x + dx, y - dy) && !free(x, y - dy)) {
+                return Some(current);
+            }
+            if jump(grid, current, [dx, 0], goal, expansions).is_some() ||
+                jump(grid, current, [0, dy], goal, expansions).is_some() {
+                return Some(current);
+            }
+            if expansions.stopped().is_some() {
+                return None;
+            }
+        } else if dx != 0 {
+            if (free(x + dx, y + 1) && !free(x, y + 1)) ||
+                (free(x + dx, y - 1) && !free(x, y - 1)) {
+                return Some(current);
+            }
+        } else if (free(x + 1, y + dy) && !free(x + 1, y)) ||
+            (free(x - 1, y + dy) && !free(x - 1, y)) {
+            return Some(current);
+        }
+    }
+}
+
+/// Follows the parent links back from `end` and returns the cells in start to end order.
+fn unwind(parents: &HashMap<Cell, Cell>, end: Cell) -> Vec<Cell> {
+    let mut path = vec![end];
+    let mut current = end;
+    while let Some(&parent) = parents.get(&current) {
+        path.push(parent);
+        current = parent;
+    }
+    path.reverse();
+    path
+}
+
+/// Fills in every cell between consecutive waypoints that lie on a straight or diagonal line.
+fn interpolate(waypoints: &[Cell]) -> Vec<Cell> {
+    let mut path = Vec::new();
+    for (i, &point) in waypoints.iter().enumerate() {
+        if i == 0 {
+            path.push(point);
+            continue;
+        }
+        let mut current = waypoints[i - 1];
+        let dx = (point[0] as isize - current[0] as isize).signum();
+        let dy = (point[1] as isize - current[1] as isize).signum();
+        while current != point {
+            current = [(current[0] as isize + dx) as usize, (current[1] as isize + dy) as usize];
+            path.push(current);
+        }
+    }
+    path
+}
+
+/// An entry in the open list of a best-first search, ordered so the lowest estimate pops first.
+#[derive(PartialEq, Eq)]
+struct Open {
+    estimate: u64,
+    cost: u64,
+    cell: Cell,
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        match other.estimate.cmp(&self.estimate) {
+            // Prefer the deeper entry on ties, as it is closer to the goal.
+            Ordering::Equal => self.cost.cmp(&other.cost),
+            ordering => ordering,
+        }
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
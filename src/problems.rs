@@ -1,34 +1,148 @@
 use super::Problem;
-use std::collections::hash_map::Entry;
 use rocket_contrib::JSON;
-use rocket::http::Status;
-use rocket::response::status;
+use rocket::request::{FormItems, FromForm, FromFormValue};
 use rocket::State;
+use error::Error;
+use jobs::Jobs;
+use journal::Author;
+use revision::IfMatch;
+use snapshot::Snapshot;
+use templates::{self, Templates};
 
-#[get("/")]
+/// Query parameters accepted when creating a problem.
+struct NewProblem {
+    /// The template to copy, instead of starting out empty.
+    template: Result<Option<String>, Error>,
+}
+
+impl<'f> FromForm<'f> for NewProblem {
+    type Error = ();
+
+    /// Never fails, keeping a bad parameter in `template` instead. Failing would pass the
+    /// request on to `post`, which would quietly create an empty problem.
+    fn from_form_items(items: &mut FormItems<'f>) -> Result<NewProblem, ()> {
+        let mut template = Ok(None);
+        for (key, value) in items {
+            let parsed = match key {
+                "template" => {
+                    String::from_form_value(value)
+                        .map(Some)
+                        .map_err(|_| Error::invalid(key, "the template name is not valid"))
+                }
+                _ => Err(Error::unknown_parameter(key)),
+            };
+            if template.is_ok() {
+                template = parsed;
+            }
+        }
+        Ok(NewProblem { template: template })
+    }
+}
+
+/// Query parameters accepted when cloning or renaming a problem.
+#[derive(FromForm)]
+struct Target {
+    /// The name of the copy, or the new name.
+    to: Option<String>,
+}
+
+/// Query parameters accepted when listing problems.
+#[derive(FromForm)]
+struct ProblemQuery {
+    /// Only problems with this tag.
+    tag: Option<String>,
+}
+
+#[get("/?<query>", rank = 1)]
+fn get_query(state: State<super::State>, query: ProblemQuery) -> JSON<Vec<String>> {
+    JSON(list(&state, query.tag.as_ref().map(String::as_str)))
+}
+
+#[get("/", rank = 2)]
 fn get(state: State<super::State>) -> JSON<Vec<String>> {
-    JSON(state.lock().unwrap().keys().cloned().collect())
+    JSON(list(&state, None))
+}
+
+/// The names of the problems with `tag`, most recently modified first.
+fn list(state: &super::State, tag: Option<&str>) -> Vec<String> {
+    let mut problems: Vec<_> = state.list()
+        .into_iter()
+        .filter(|&(_, ref metadata)| tag.map_or(true, |tag| metadata.tags.contains(tag)))
+        .collect();
+    problems.sort_by(|a, b| (b.1.modified, &a.0).cmp(&(a.1.modified, &b.0)));
+    problems.into_iter().map(|(name, _)| name).collect()
+}
+
+#[post("/<problem>?<options>", rank = 1)]
+fn post_from(state: State<super::State>,
+             templates: State<Templates>,
+             author: Author,
+             problem: &str,
+             options: NewProblem) -> Result<(), Error> {
+    let new = match options.template? {
+        Some(template) => templates.get(&template)?.into_problem(),
+        None => Problem::default(),
+    };
+    create(&state, &author, problem, new)
+}
+
+#[post("/<problem>", rank = 2)]
+fn post(state: State<super::State>, author: Author, problem: &str) -> Result<(), Error> {
+    create(&state, &author, problem, Problem::default())
+}
+
+/// Copies a problem to a new one, which starts out with no history and its own caches.
+#[post("/<problem>/Clone?<target>")]
+fn clone_to(state: State<super::State>,
+            author: Author,
+            problem: &str,
+            target: Target) -> Result<(), Error> {
+    let to = target.to.ok_or_else(|| Error::invalid("to", "the name of the copy is missing"))?;
+    // Going through a snapshot leaves behind everything that isn't part of the problem itself.
+    let copy = state.read(problem, |problem| Ok(Snapshot::of(problem)))?;
+    create(&state, &author, &to, copy.into_problem())
+}
+
+/// Gives a problem a new name, keeping its contents and history.
+#[post("/<problem>/Rename?<target>")]
+fn rename(state: State<super::State>,
+          jobs: State<Jobs>,
+          if_match: IfMatch,
+          author: Author,
+          problem: &str,
+          target: Target) -> Result<(), Error> {
+    let to = target.to.ok_or_else(|| Error::invalid("to", "the new name is missing"))?;
+    check_name(&to)?;
+    state.rename(problem, &to, &author, |existing| if_match.check(existing))?;
+    jobs.rename(problem, &to);
+    Ok(())
 }
 
-#[post("/<problem>")]
-fn post(state: State<super::State>, problem: &str) -> status::Custom<()> {
+fn create(state: &super::State, author: &Author, name: &str, problem: Problem)
+        -> Result<(), Error> {
+    check_name(name)?;
     // Attempt to add the new problem.
-    match state.lock().unwrap().entry(String::from(problem)) {
-        Entry::Occupied(_) => status::Custom(Status::Conflict, ()),
-        Entry::Vacant(v) => {
-            v.insert(Problem::default());
-            status::Custom(Status::Ok, ())
-        }
+    state.insert(name, problem, author)
+}
+
+fn check_name(name: &str) -> Result<(), Error> {
+    if name == templates::RESERVED {
+        return Err(Error::invalid("problem",
+                                  format!("\"{}\" is reserved for templates", name)));
     }
+    Ok(())
 }
 
 #[delete("/<problem>")]
-fn delete(state: State<super::State>, problem: &str) -> status::Custom<()> {
-    // Attempt to remove the problem.
-    match state.lock().unwrap().remove(problem) {
-        Some(_) => status::Custom(Status::Ok, ()),
-        None => status::Custom(Status::NotFound, ()),
-    }
+fn delete(state: State<super::State>,
+          jobs: State<Jobs>,
+          if_match: IfMatch,
+          author: Author,
+          problem: &str) -> Result<(), Error> {
+    // Only remove the problem if it is still the revision the client expects.
+    state.remove_if(problem, &author, |existing| if_match.check(existing))?;
+    jobs.remove(problem);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -37,7 +151,9 @@ mod test {
     extern crate serde_json;
     use rocket::Response;
     use rocket::testing::MockRequest;
-    use rocket::http::{Status, Method};
+    use rocket::http::{Status, Method, ContentType};
+    use std::thread;
+    use std::time::Duration;
 
     fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
         response.body()
@@ -76,6 +192,84 @@ mod test {
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(body_deser(&mut response), Some(vec![String::from("test")]));
 
+        // Clone "test" once it has a robot.
+        let mut request = MockRequest::new(Method::Post, "/test/Robot")
+            .header(ContentType::JSON)
+            .body(r#"{ "point": { "x": 1.0, "y": 1.0 }, "radius": 0.5 }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/test/Clone?to=copy");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/copy/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("ETag").unwrap().ends_with("-0\""));
+
+        // The copy changes independently of the original.
+        let mut request = MockRequest::new(Method::Delete, "/copy/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Cloning onto an existing problem, or from a missing one, fails.
+        let mut request = MockRequest::new(Method::Post, "/test/Clone?to=copy");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Conflict);
+        let mut request = MockRequest::new(Method::Post, "/blah/Clone?to=other");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let mut request = MockRequest::new(Method::Delete, "/copy");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Rename "test", which keeps what is in it.
+        let mut request = MockRequest::new(Method::Post, "/test/Rename?to=renamed");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/renamed/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Renaming onto another problem fails.
+        let mut request = MockRequest::new(Method::Post, "/other");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/renamed/Rename?to=other");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Conflict);
+
+        // Problems are listed most recently modified first, and can be filtered by tag.
+        thread::sleep(Duration::from_millis(5));
+        let mut request = MockRequest::new(Method::Put, "/renamed/Metadata")
+            .header(ContentType::JSON)
+            .body(r#"{ "tags": ["base"] }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(body_deser(&mut response),
+                   Some(vec![String::from("renamed"), String::from("other")]));
+        let mut request = MockRequest::new(Method::Get, "/?tag=base");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(body_deser(&mut response), Some(vec![String::from("renamed")]));
+        let mut request = MockRequest::new(Method::Get, "/?tag=none");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(body_deser::<Vec<String>>(&mut response), Some(vec![]));
+
+        // Put things back as they were.
+        let mut request = MockRequest::new(Method::Delete, "/other");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/renamed/Rename?to=test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
         // Remove a non-existing problem "blah".
         let mut request = MockRequest::new(Method::Delete, "/blah");
         let response = request.dispatch_with(&rocket);
@@ -0,0 +1,523 @@
+use rocket::http::Status;
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use super::Problem;
+use dstar::DStarLite;
+use error::Error;
+use grid::Grid;
+use history::History;
+use journal::{Author, Change, Event};
+use metadata::{self, Metadata};
+use revision::Version;
+use snapshot::Snapshot;
+use storage::{self, Record, Storage};
+
+/// The problems being served, each behind its own lock.
+///
+/// The map itself is only locked for writing while problems are created or removed, so
+/// requests on different problems never wait on each other.
+///
+/// Every change is written through to `storage` before the request that made it returns.
+pub struct Registry {
+    problems: RwLock<HashMap<String, Arc<RwLock<Slot>>>>,
+    storage: Box<Storage>,
+    /// When the newest problem was created, which later ones are created after.
+    last_created: Mutex<u64>,
+}
+
+/// A problem along with the earlier versions it can be taken back to.
+///
+/// The history sits beside the problem rather than in it so copies of a problem don't carry
+/// it around, and replacing a whole problem doesn't lose it.
+#[derive(Default)]
+struct Slot {
+    problem: Problem,
+    history: History,
+    /// Held by requests that have to work on the problem one at a time.
+    serial: Arc<Mutex<()>>,
+}
+
+impl Slot {
+    fn new(problem: Problem) -> Arc<RwLock<Slot>> {
+        Arc::new(RwLock::new(Slot { problem: problem, ..Slot::default() }))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry {
+            problems: RwLock::new(HashMap::new()),
+            storage: Box::new(storage::Memory),
+            last_created: Mutex::new(0),
+        }
+    }
+}
+
+/// Locks for reading, ignoring poisoning so a panic in one request doesn't break later ones.
+///
+/// A problem whose lock was poisoned is rebuilt by `get` before it is used again, since the
+/// panic may have left its caches or index half updated.
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
+
+fn already_exists(name: &str) -> Error {
+    Error::already_exists("problem", format!("problem \"{}\" already exists", name))
+}
+
+impl Registry {
+    /// Loads every problem kept in `storage`.
+    pub fn new(storage: Box<Storage>) -> Result<Registry, Error> {
+        let problems: HashMap<_, _> = storage.load()?
+            .into_iter()
+            .map(|(name, problem)| (name, Slot::new(problem)))
+            .collect();
+        let last_created = problems.values()
+            .map(|slot| read(slot).problem.metadata.created)
+            .max()
+            .unwrap_or(0);
+        Ok(Registry {
+            problems: RwLock::new(problems),
+            storage: storage,
+            last_created: Mutex::new(last_created),
+        })
+    }
+
+    /// The current time, or just after the newest problem was created if that is later.
+    ///
+    /// No two problems get the same creation time, so it tells apart problems that had the
+    /// same name at different times.
+    fn creation_time(&self) -> u64 {
+        let mut last = self.last_created.lock().unwrap_or_else(|e| e.into_inner());
+        *last = cmp::max(metadata::now(), *last + 1);
+        *last
+    }
+
+    /// The name and metadata of every problem.
+    pub fn list(&self) -> Vec<(String, Metadata)> {
+        read(&self.problems).iter()
+            .map(|(name, slot)| (name.clone(), read(slot).problem.metadata.clone()))
+            .collect()
+    }
+
+    fn get(&self, name: &str) -> Result<Arc<RwLock<Slot>>, Error> {
+        let slot = read(&self.problems).get(name).cloned()
+            .ok_or_else(|| Error::problem_not_found(name))?;
+        if slot.is_poisoned() {
+            return self.recover(name, &slot);
+        }
+        Ok(slot)
+    }
+
+    /// Replaces a problem whose lock was poisoned with one rebuilt from its contents, which
+    /// drops the caches and index a panic may have left half updated.
+    ///
+    /// Requests only ever change a copy of a problem, which replaces it once the change is
+    /// saved, so the contents are still those from before the panic.
+    fn recover(&self, name: &str, poisoned: &Arc<RwLock<Slot>>)
+               -> Result<Arc<RwLock<Slot>>, Error> {
+        let mut problems = write(&self.problems);
+        match problems.get(name) {
+            // Another request got here first, or the problem was removed meanwhile.
+            Some(slot) if !Arc::ptr_eq(slot, poisoned) => return Ok(slot.clone()),
+            None => return Err(Error::problem_not_found(name)),
+            Some(_) => {}
+        }
+        let fresh = {
+            let mut guard = write(poisoned);
+            let problem = Record::of(&guard.problem).into_problem();
+            Arc::new(RwLock::new(Slot {
+                problem: problem,
+                history: mem::replace(&mut guard.history, History::default()),
+                serial: guard.serial.clone(),
+            }))
+        };
+        problems.insert(String::from(name), fresh.clone());
+        Ok(fresh)
+    }
+
+    /// Adds a problem, unless one with the same name exists already.
+    pub fn insert(&self, name: &str, problem: Problem, author: &Author) -> Result<(), Error> {
+        let mut problems = write(&self.problems);
+        if problems.contains_key(name) {
+            return Err(already_exists(name));
+        }
+        self.insert_into(&mut problems, name, problem, author).map(|_| ())
+    }
+
+    fn insert_into(&self,
+                   problems: &mut HashMap<String, Arc<RwLock<Slot>>>,
+                   name: &str,
+                   mut problem: Problem,
+                   author: &Author) -> Result<Version, Error> {
+        problem.metadata.created = self.creation_time();
+        problem.metadata.modified = problem.metadata.created;
+        let change = Change { author: author, event: Event::Created(Snapshot::of(&problem)) };
+        self.storage.save(name, &problem, &change)?;
+        let version = problem.version();
+        problems.insert(String::from(name), Slot::new(problem));
+        Ok(version)
+    }
+
+    /// Replaces a problem with `problem` if `check` accepts it, or adds `problem` if there is
+    /// none, without another request adding one in between.
+    ///
+    /// `check` is given `None` when there is nothing to replace. A replacement keeps the
+    /// metadata of the problem it replaces and goes up a revision, and `event` is what is
+    /// saved for it.
+    pub fn put<F>(&self, name: &str, problem: Problem, event: Event, author: &Author, check: F)
+                  -> Result<Version, Error>
+        where F: FnOnce(Option<&Problem>) -> Result<(), Error>
+    {
+        let mut problems = write(&self.problems);
+        let slot = match problems.get(name).cloned() {
+            Some(slot) => slot,
+            None => {
+                check(None)?;
+                return self.insert_into(&mut problems, name, problem, author);
+            }
+        };
+        self.change_slot(name, &slot, author, |existing| {
+            check(Some(existing))?;
+            let revision = existing.revision + 1;
+            let metadata = existing.metadata.clone();
+            *existing = problem;
+            existing.revision = revision;
+            existing.metadata = metadata;
+            Ok((existing.version(), event))
+        })
+    }
+
+    /// Removes a problem if `check` accepts it.
+    pub fn remove_if<F>(&self, name: &str, author: &Author, check: F) -> Result<(), Error>
+        where F: FnOnce(&Problem) -> Result<(), Error>
+    {
+        let mut problems = write(&self.problems);
+        match problems.get(name) {
+            Some(slot) => check(&read(slot).problem)?,
+            None => return Err(Error::problem_not_found(name)),
+        }
+        self.storage.remove(name, &Change { author: author, event: Event::Deleted })?;
+        problems.remove(name);
+        Ok(())
+    }
+
+    /// Moves a problem and its history to a new name, if `check` accepts it.
+    pub fn rename<F>(&self, name: &str, to: &str, author: &Author, check: F) -> Result<(), Error>
+        where F: FnOnce(&Problem) -> Result<(), Error>
+    {
+        let mut problems = write(&self.problems);
+        if problems.contains_key(to) {
+            return Err(already_exists(to));
+        }
+        let slot = problems.get(name).cloned().ok_or_else(|| Error::problem_not_found(name))?;
+        {
+            let guard = read(&slot);
+            check(&guard.problem)?;
+            let change = Change { author: author, event: Event::Renamed(String::from(to)) };
+            self.storage.rename(name, to, &guard.problem, &change)?;
+        }
+        problems.remove(name);
+        problems.insert(String::from(to), slot);
+        Ok(())
+    }
+
+    /// Runs `f` with shared access to a problem.
+    pub fn read<T, F>(&self, name: &str, f: F) -> Result<T, Error>
+        where F: FnOnce(&Problem) -> Result<T, Error>
+    {
+        let slot = self.get(name)?;
+        let guard = read(&slot);
+        f(&guard.problem)
+    }
+
+    /// Runs `f` while no other request runs it for the same problem, for work such as
+    /// repairing the D* Lite search that has to be done one request at a time.
+    ///
+    /// The problem itself isn't locked, so it can still be read and changed meanwhile.
+    pub fn one_at_a_time<T, F>(&self, name: &str, f: F) -> Result<T, Error>
+        where F: FnOnce() -> Result<T, Error>
+    {
+        let serial = read(&self.get(name)?).serial.clone();
+        let _guard = serial.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+
+    /// A copy of a problem to plan on, which takes the problem's D* Lite search along if
+    /// `search` is set, so the search can be repaired and handed back with `keep`.
+    pub fn checkout(&self, name: &str, search: bool) -> Result<Problem, Error> {
+        let slot = self.get(name)?;
+        if !search {
+            return Ok(read(&slot).problem.clone());
+        }
+        let mut guard = write(&slot);
+        let mut copy = guard.problem.clone();
+        copy.dstar = guard.problem.dstar.take();
+        Ok(copy)
+    }
+
+    /// Hands back the grid and D* Lite search built by planning on a copy of a problem at
+    /// `version`, unless the problem has changed since.
+    pub fn keep(&self,
+                name: &str,
+                version: Version,
+                grid: Option<Arc<Grid>>,
+                search: Option<DStarLite>) {
+        let slot = match self.get(name) {
+            Ok(slot) => slot,
+            Err(_) => return,
+        };
+        let mut guard = write(&slot);
+        if guard.problem.version() != version {
+            return;
+        }
+        if let Some(grid) = grid {
+            guard.problem.grid = Some(grid);
+        }
+        if let Some(search) = search {
+            guard.problem.dstar = search;
+        }
+    }
+
+    /// Runs `f` with exclusive access to a problem to make the change it returns, and saves
+    /// the change.
+    ///
+    /// `f` works on a copy that only replaces the problem once the change is saved, so a
+    /// change that can't be saved doesn't happen at all. The problem as it was beforehand
+    /// goes into its history.
+    pub fn change<T, F>(&self, name: &str, author: &Author, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Problem) -> Result<(T, Event), Error>
+    {
+        let slot = self.get(name)?;
+        self.change_slot(name, &slot, author, f)
+    }
+
+    fn change_slot<T, F>(&self, name: &str, slot: &RwLock<Slot>, author: &Author, f: F)
+                         -> Result<T, Error>
+        where F: FnOnce(&mut Problem) -> Result<(T, Event), Error>
+    {
+        let mut guard = write(slot);
+        let slot = &mut *guard;
+        // Move the caches over rather than sharing them, so the copy can keep them up to date.
+        let mut changed = slot.problem.clone();
+        changed.grid = slot.problem.grid.take();
+        changed.dstar = slot.problem.dstar.take();
+        let (result, event) = match f(&mut changed) {
+            Ok(done) => done,
+            Err(error) => {
+                // `f` leaves the problem untouched when it fails, caches included.
+                slot.problem.grid = changed.grid.take();
+                slot.problem.dstar = changed.dstar.take();
+                return Err(error);
+            }
+        };
+        changed.metadata.modified = metadata::now();
+        self.storage.save(name, &changed, &Change { author: author, event: event })?;
+        let before = mem::replace(&mut slot.problem, changed);
+        slot.history.record(Record::from_problem(before));
+        Ok(result)
+    }
+
+    /// Takes a problem back to the version before its last change, if `check` accepts it.
+    ///
+    /// Returns the new version, since undoing is itself a change.
+    pub fn undo<F>(&self, name: &str, author: &Author, check: F) -> Result<Version, Error>
+        where F: FnOnce(&Problem) -> Result<(), Error>
+    {
+        self.restore(name, author, check, "undo", History::previous, History::undo)
+    }
+
+    /// Makes a change again after it was undone, if `check` accepts it.
+    pub fn redo<F>(&self, name: &str, author: &Author, check: F) -> Result<Version, Error>
+        where F: FnOnce(&Problem) -> Result<(), Error>
+    {
+        self.restore(name, author, check, "redo", History::next, History::redo)
+    }
+
+    /// Makes the version `peek` finds current, and then moves through the history with
+    /// `step`.
+    fn restore<F, G, H>(&self,
+                        name: &str,
+                        author: &Author,
+                        check: F,
+                        action: &str,
+                        peek: G,
+                        step: H) -> Result<Version, Error>
+        where F: FnOnce(&Problem) -> Result<(), Error>,
+              G: FnOnce(&History) -> Option<&Record>,
+              H: FnOnce(&mut History, Record) -> Option<Record>
+    {
+        let slot = self.get(name)?;
+        let mut guard = write(&slot);
+        let slot = &mut *guard;
+        check(&slot.problem)?;
+        let mut version = match peek(&slot.history) {
+            Some(version) => version.clone(),
+            None => {
+                return Err(Error::new(Status::Conflict,
+                                      "no_history",
+                                      format!("there is nothing to {}", action)));
+            }
+        };
+        version.revision = slot.problem.revision + 1;
+        version.metadata.modified = metadata::now();
+        let problem = version.clone().into_problem();
+        // Leave the problem and its history alone until the change is saved, so a change
+        // that can't be saved doesn't happen at all.
+        let change = Change { author: author, event: Event::Restored(version) };
+        self.storage.save(name, &problem, &change)?;
+        let replaced = mem::replace(&mut slot.problem, problem);
+        step(&mut slot.history, Record::from_problem(replaced));
+        Ok(slot.problem.version())
+    }
+
+    /// Runs `f` with the history of a problem and the problem as it is now.
+    pub fn history<T, F>(&self, name: &str, f: F) -> Result<T, Error>
+        where F: FnOnce(&History, &Problem) -> Result<T, Error>
+    {
+        let slot = self.get(name)?;
+        let guard = read(&slot);
+        f(&guard.history, &guard.problem)
+    }
+
+    /// A copy of a problem that can be worked on without holding any lock.
+    pub fn snapshot(&self, name: &str) -> Result<Problem, Error> {
+        self.read(name, |problem| Ok(problem.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proto;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use super::Registry;
+    use super::super::Problem;
+    use error::Error;
+    use journal::{Author, Change, Event};
+    use spatial::Rect;
+    use storage::{storage_error, Storage};
+
+    /// Storage that fails to save while `failing` is set.
+    struct Flaky {
+        failing: Arc<AtomicBool>,
+    }
+
+    impl Flaky {
+        fn result(&self) -> Result<(), Error> {
+            if self.failing.load(Ordering::SeqCst) {
+                Err(storage_error("the disk is full"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Storage for Flaky {
+        fn load(&self) -> Result<Vec<(String, Problem)>, Error> {
+            Ok(vec![])
+        }
+
+        fn save(&self, _: &str, _: &Problem, _: &Change) -> Result<(), Error> {
+            self.result()
+        }
+
+        fn remove(&self, _: &str, _: &Change) -> Result<(), Error> {
+            self.result()
+        }
+
+        fn rename(&self, _: &str, _: &str, _: &Problem, _: &Change) -> Result<(), Error> {
+            self.result()
+        }
+    }
+
+    #[test]
+    fn test() {
+        let registry = Arc::new(Registry::default());
+        let author = Author::default();
+        registry.insert("a", Default::default(), &author).unwrap();
+        registry.insert("b", Default::default(), &author).unwrap();
+        assert!(registry.insert("a", Default::default(), &author).is_err());
+
+        // Holding one problem doesn't keep another from being changed.
+        registry.read("a", |_| {
+            registry.change("b", &author, |b| {
+                b.revision += 1;
+                Ok(((), Event::Planner(b.planner.clone())))
+            })
+        }).unwrap();
+
+        // A panic while "a" is locked poisons its lock, but it can still be used afterwards,
+        // as it was before the change that panicked.
+        let panicking = registry.clone();
+        let result = thread::spawn(move || {
+            panicking.change("a", &Author::default(), |a| -> Result<((), Event), _> {
+                a.insert_obstacle(String::from("wall"), proto::Obstacle {
+                    width: 1.0,
+                    length: 1.0,
+                    point: proto::Point { x: 0.0, y: 0.0 },
+                });
+                panic!("handler failed")
+            })
+        }).join();
+        assert!(result.is_err());
+        assert_eq!(registry.read("a", |a| Ok(a.revision)).unwrap(), 0);
+        assert!(!registry.read("a", |a| Ok(a.obstacles.contains_key("wall"))).unwrap());
+        let area = Rect::of_obstacle(&proto::Obstacle {
+            width: 4.0,
+            length: 4.0,
+            point: proto::Point { x: 0.0, y: 0.0 },
+        });
+        assert!(registry.read("a", |a| Ok(a.obstacle_index.candidates(&area))).unwrap()
+            .is_empty());
+        assert_eq!(registry.read("b", |b| Ok(b.revision)).unwrap(), 1);
+
+        registry.remove_if("a", &author, |_| Ok(())).unwrap();
+        assert_eq!(registry.list().into_iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                   vec![String::from("b")]);
+        assert!(registry.snapshot("a").is_err());
+
+        // Work that has to be done one request at a time never overlaps.
+        let running = Arc::new(AtomicBool::new(false));
+        let threads: Vec<_> = (0..4).map(|_| {
+            let registry = registry.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                registry.one_at_a_time("b", || {
+                    assert!(!running.swap(true, Ordering::SeqCst));
+                    thread::sleep(Duration::from_millis(10));
+                    running.store(false, Ordering::SeqCst);
+                    Ok(())
+                }).unwrap();
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Changes and undos that can't be saved leave the problem and its history as they were.
+        let failing = Arc::new(AtomicBool::new(false));
+        let registry = Registry::new(Box::new(Flaky { failing: failing.clone() })).unwrap();
+        registry.insert("c", Default::default(), &author).unwrap();
+        let bump = |problem: &mut Problem| -> Result<((), Event), Error> {
+            problem.revision += 1;
+            Ok(((), Event::Planner(problem.planner.clone())))
+        };
+        registry.change("c", &author, &bump).unwrap();
+        failing.store(true, Ordering::SeqCst);
+        assert!(registry.change("c", &author, &bump).is_err());
+        assert!(registry.undo("c", &author, |_| Ok(())).is_err());
+        assert_eq!(registry.read("c", |c| Ok(c.revision)).unwrap(), 1);
+        failing.store(false, Ordering::SeqCst);
+        assert_eq!(registry.undo("c", &author, |_| Ok(())).unwrap().revision, 2);
+        assert_eq!(registry.redo("c", &author, |_| Ok(())).unwrap().revision, 3);
+    }
+}
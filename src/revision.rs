@@ -0,0 +1,158 @@
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use super::Problem;
+use error::Error;
+
+/// A revision of a particular problem.
+///
+/// Revisions start over from 0 when a problem is deleted and another one takes its name, so
+/// the time the problem was created, which the registry keeps unique, tells them apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Version {
+    pub created: u64,
+    pub revision: u64,
+}
+
+impl Version {
+    pub fn of(problem: &Problem) -> Version {
+        Version { created: problem.metadata.created, revision: problem.revision }
+    }
+
+    /// The entity tag of the version.
+    pub fn etag(&self) -> String {
+        format!("\"{}-{}\"", self.created, self.revision)
+    }
+}
+
+/// A response carrying the version of the problem it came from as its `ETag`.
+pub struct Tagged<R>(pub R, pub Version);
+
+impl<'r, R: Responder<'r>> Responder<'r> for Tagged<R> {
+    fn respond(self) -> response::Result<'r> {
+        let mut response = self.0.respond()?;
+        response.set_raw_header("ETag", self.1.etag());
+        Ok(response)
+    }
+}
+
+/// The `If-Match` header of a request, if it sent one.
+pub struct IfMatch(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<IfMatch, ()> {
+        Outcome::Success(IfMatch(request.headers().get_one("If-Match").map(String::from)))
+    }
+}
+
+impl IfMatch {
+    /// Fails with 412 unless the header is absent, `*`, or lists the current version of the
+    /// problem.
+    pub fn check(&self, problem: &Problem) -> Result<(), Error> {
+        let header = match self.0 {
+            Some(ref header) => header,
+            None => return Ok(()),
+        };
+        let revision = problem.revision;
+        let current = problem.version().etag();
+        let matches = header.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag == current);
+        if matches {
+            Ok(())
+        } else {
+            Err(Error::new(Status::PreconditionFailed,
+                           "revision_mismatch",
+                           format!("the problem has changed, it is now at revision {}",
+                                   revision))
+                .with_details(revision))
+        }
+    }
+
+    /// Fails with 412 if the header is present, since there is no version for it to match.
+    pub fn check_missing(&self) -> Result<(), Error> {
+        match self.0 {
+            Some(_) => {
+                Err(Error::new(Status::PreconditionFailed,
+                               "revision_mismatch",
+                               "there is no current revision to match"))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rocket::testing::MockRequest;
+    use rocket::http::{Status, Method, ContentType, Header};
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Add "test" to the problems, which starts out at revision 0.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles");
+        let response = request.dispatch_with(&rocket);
+        let first = String::from(response.headers().get_one("ETag").unwrap());
+        assert!(first.ends_with("-0\""));
+        let tag = |revision: u64| format!("{}-{}\"", &first[..first.len() - 3], revision);
+
+        // A change made against the current revision succeeds and bumps it.
+        let obstacle = r#"{ "width": 1.0, "length": 1.0, "point": { "x": 0.0, "y": 0.0 } }"#;
+        let mut request = MockRequest::new(Method::Post, "/test/Obstacles/a")
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", tag(0)))
+            .body(obstacle);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ETag"), Some(tag(1).as_str()));
+
+        // Someone still looking at revision 0 can't overwrite it.
+        let mut request = MockRequest::new(Method::Put, "/test/Obstacles/a")
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", tag(0)))
+            .body(obstacle);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::PreconditionFailed);
+
+        // Without If-Match or with a wildcard the change goes through.
+        let mut request = MockRequest::new(Method::Put, "/test/Obstacles/a")
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", "*"))
+            .body(obstacle);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Delete, "/test/Obstacles/a");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ETag"), Some(tag(3).as_str()));
+
+        // Failed changes don't bump the revision.
+        let mut request = MockRequest::new(Method::Delete, "/test/Obstacles/a");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let mut request = MockRequest::new(Method::Get, "/test/Snapshot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.headers().get_one("ETag"), Some(tag(3).as_str()));
+
+        // A new problem under the same name doesn't match tags of the old one.
+        let mut request = MockRequest::new(Method::Delete, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Delete, "/test")
+            .header(Header::new("If-Match", tag(0)));
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::PreconditionFailed);
+    }
+}
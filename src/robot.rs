@@ -1,41 +1,45 @@
-use rocket::http::Status;
-use rocket::response::status;
 use rocket::State;
 use rocket_contrib::JSON;
 use proto;
+use error::Error;
+use operation::{self, Operation};
+use journal::Author;
+use revision::{IfMatch, Tagged};
+
+#[get("/<problem>/Robot")]
+fn get(state: State<super::State>, problem: &str) -> Result<Tagged<JSON<proto::Robot>>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        problem.robot.clone()
+            .map(|robot| Tagged(JSON(robot), problem.version()))
+            .ok_or_else(|| Error::unset("robot"))
+    })
+}
+
+#[delete("/<problem>/Robot")]
+fn delete(state: State<super::State>,
+          if_match: IfMatch,
+          author: Author,
+          problem: &str) -> Result<Tagged<()>, Error> {
+    operation::apply(&state, problem, &if_match, &author, Operation::DeleteRobot)
+}
 
 #[post("/<problem>/Robot", data = "<robot>")]
 fn post(state: State<super::State>,
-       problem: &str,
-       robot: JSON<proto::Robot>) -> status::Custom<()> {
-    // Attempt to access the problem.
-    if let Some(problem) = state.lock().unwrap().get_mut(problem) {
-        if problem.robot.is_some() {
-            status::Custom(Status::Conflict, ())
-        } else {
-            problem.robot = Some(robot.0);
-            status::Custom(Status::Ok, ())
-        }
-    } else {
-        status::Custom(Status::NotFound, ())
-    }
+        if_match: IfMatch,
+        author: Author,
+        problem: &str,
+        robot: JSON<proto::Robot>) -> Result<Tagged<()>, Error> {
+    operation::apply(&state, problem, &if_match, &author, Operation::AddRobot(robot.0))
 }
 
 #[put("/<problem>/Robot", data = "<robot>")]
 fn put(state: State<super::State>,
-        problem: &str,
-        robot: JSON<proto::Robot>) -> status::Custom<()> {
-    // Attempt to access the problem.
-    if let Some(problem) = state.lock().unwrap().get_mut(problem) {
-        if problem.robot.is_some() {
-            problem.robot = Some(robot.0);
-            status::Custom(Status::Ok, ())
-        } else {
-            status::Custom(Status::Conflict, ())
-        }
-    } else {
-        status::Custom(Status::NotFound, ())
-    }
+       if_match: IfMatch,
+       author: Author,
+       problem: &str,
+       robot: JSON<proto::Robot>) -> Result<Tagged<()>, Error> {
+    operation::apply(&state, problem, &if_match, &author, Operation::ReplaceRobot(robot.0))
 }
 
 #[cfg(test)]
@@ -43,9 +47,19 @@ mod test {
     extern crate serde;
     extern crate serde_json;
     use rocket::testing::MockRequest;
+    use rocket::Response;
     use rocket::http::{Status, Method, ContentType};
     use proto;
 
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
     #[test]
     fn test() {
         // Make the mock server.
@@ -105,5 +119,25 @@ mod test {
             }).unwrap());
         let response = request.dispatch_with(&rocket);
         assert_eq!(response.status(), Status::Ok);
+
+        // Get the robot back.
+        let mut request = MockRequest::new(Method::Get, "/test/Robot");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let body = body_deser::<proto::Robot>(&mut response).unwrap();
+        assert_eq!(body.radius, 1.0);
+
+        // Clear the robot.
+        let mut request = MockRequest::new(Method::Delete, "/test/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Now that it is cleared, getting or clearing it again fails.
+        let mut request = MockRequest::new(Method::Get, "/test/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let mut request = MockRequest::new(Method::Delete, "/test/Robot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,112 @@
+use rocket::State;
+use rocket_contrib::JSON;
+use error::Error;
+use journal::{Author, Event};
+use revision::{IfMatch, Tagged};
+
+/// Per-problem settings for the path planner.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PlannerSettings {
+    /// Size of a grid cell. When unset a quarter of the robot radius is used.
+    pub resolution: Option<f64>,
+}
+
+impl PlannerSettings {
+    /// Whether every setting is within its valid range.
+    pub fn is_valid(&self) -> bool {
+        self.resolution.map_or(true, |r| r > 0.0 && r.is_finite())
+    }
+}
+
+#[get("/<problem>/Planner")]
+fn get(state: State<super::State>, problem: &str)
+        -> Result<Tagged<JSON<PlannerSettings>>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        Ok(Tagged(JSON(problem.planner.clone()), problem.version()))
+    })
+}
+
+#[put("/<problem>/Planner", data = "<settings>")]
+fn put(state: State<super::State>,
+       if_match: IfMatch,
+       author: Author,
+       problem: &str,
+       settings: JSON<PlannerSettings>) -> Result<Tagged<()>, Error> {
+    // Attempt to access the problem.
+    state.change(problem, &author, |problem| {
+        if_match.check(problem)?;
+        if settings.is_valid() {
+            problem.planner = settings.0.clone();
+            problem.revision += 1;
+            Ok((Tagged((), problem.version()), Event::Planner(settings.0)))
+        } else {
+            Err(Error::invalid("resolution", "the resolution must be a positive distance"))
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType};
+    use super::PlannerSettings;
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Get the settings of "test" before "test" exists so it should fail.
+        let mut request = MockRequest::new(Method::Get, "/test/Planner");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Add "test" to the problems.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // The resolution starts out unset.
+        let mut request = MockRequest::new(Method::Get, "/test/Planner");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_deser::<PlannerSettings>(&mut response).unwrap().resolution, None);
+
+        // Put a negative resolution, which will fail.
+        let mut request = MockRequest::new(Method::Put, "/test/Planner")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PlannerSettings{
+                resolution: Some(-1.0),
+            }).unwrap());
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Put a valid resolution.
+        let mut request = MockRequest::new(Method::Put, "/test/Planner")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PlannerSettings{
+                resolution: Some(0.05),
+            }).unwrap());
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Make sure the resolution was stored.
+        let mut request = MockRequest::new(Method::Get, "/test/Planner");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_deser::<PlannerSettings>(&mut response).unwrap().resolution, Some(0.05));
+    }
+}
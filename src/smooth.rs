@@ -0,0 +1,140 @@
+use grid::{Cell, Grid};
+
+/// Number of straight pieces each spline segment is flattened into before resampling.
+const SPLINE_SUBDIVISIONS: usize = 32;
+
+/// Post-processing applied to a path after the search, regardless of which planner found it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    /// Return the path exactly as the planner produced it.
+    None,
+    /// Drop every waypoint that can be skipped with a collision-free straight line.
+    Shortcut,
+    /// Shortcut the path, then fit a Catmull-Rom spline and resample it.
+    Spline,
+}
+
+impl Smoothing {
+    /// Looks up a smoothing stage by the name used in the `smooth` query parameter.
+    pub fn by_name(name: &str) -> Option<Smoothing> {
+        match name {
+            "none" => Some(Smoothing::None),
+            "shortcut" => Some(Smoothing::Shortcut),
+            "spline" => Some(Smoothing::Spline),
+            _ => None,
+        }
+    }
+
+    /// Runs the stage on a path of waypoint cells and converts it to positions.
+    ///
+    /// Spline samples are spaced `spacing` apart. If the spline would clip an obstacle, the
+    /// shortcut path is returned instead.
+    pub fn apply(&self, grid: &Grid, path: &[Cell], spacing: f64) -> Vec<[f64; 2]> {
+        match *self {
+            Smoothing::None => path.iter().map(|&c| grid.from_coords(c)).collect(),
+            Smoothing::Shortcut => {
+                shortcut(grid, path).into_iter().map(|c| grid.from_coords(c)).collect()
+            }
+            Smoothing::Spline => {
+                let corners: Vec<[f64; 2]> = shortcut(grid, path).into_iter()
+                    .map(|c| grid.from_coords(c))
+                    .collect();
+                spline(grid, &corners, spacing).unwrap_or(corners)
+            }
+        }
+    }
+}
+
+/// Greedily connects each kept waypoint to the farthest later waypoint it has line of sight to.
+pub fn shortcut(grid: &Grid, path: &[Cell]) -> Vec<Cell> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+    let mut kept = vec![path[0]];
+    let mut anchor = 0;
+    while anchor < path.len() - 1 {
+        let mut next = anchor + 1;
+        for candidate in (anchor + 2..path.len()).rev() {
+            if grid.line_of_sight(path[anchor], path[candidate]) {
+                next = candidate;
+                break;
+            }
+        }
+        kept.push(path[next]);
+        anchor = next;
+    }
+    kept
+}
+
+/// Fits a uniform Catmull-Rom spline through the waypoints and resamples it.
+///
+/// Returns `None` if any part of the curve passes through an occupied cell.
+pub fn spline(grid: &Grid, waypoints: &[[f64; 2]], spacing: f64) -> Option<Vec<[f64; 2]>> {
+    if waypoints.len() < 3 {
+        return Some(resample(waypoints, spacing));
+    }
+
+    // Flatten the spline into a dense polyline.
+    let mut dense = vec![waypoints[0]];
+    for i in 0..waypoints.len() - 1 {
+        // The end points are duplicated so the curve starts and ends on them.
+        let p0 = waypoints[if i == 0 { 0 } else { i - 1 }];
+        let p1 = waypoints[i];
+        let p2 = waypoints[i + 1];
+        let p3 = waypoints[if i + 2 < waypoints.len() { i + 2 } else { i + 1 }];
+        for step in 1..SPLINE_SUBDIVISIONS + 1 {
+            let t = step as f64 / SPLINE_SUBDIVISIONS as f64;
+            dense.push(catmull_rom(p0, p1, p2, p3, t));
+        }
+    }
+
+    // Every piece of the polyline must be collision-free.
+    for pair in dense.windows(2) {
+        match (grid.to_coords(pair[0]), grid.to_coords(pair[1])) {
+            (Some(a), Some(b)) => {
+                if !grid.line_of_sight(a, b) {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(resample(&dense, spacing))
+}
+
+/// Evaluates a uniform Catmull-Rom segment between `p1` and `p2` at `t` in `[0, 1]`.
+fn catmull_rom(p0: [f64; 2], p1: [f64; 2], p2: [f64; 2], p3: [f64; 2], t: f64) -> [f64; 2] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let axis = |i: usize| {
+        0.5 * (2.0 * p1[i] +
+            (-p0[i] + p2[i]) * t +
+            (2.0 * p0[i] - 5.0 * p1[i] + 4.0 * p2[i] - p3[i]) * t2 +
+            (-p0[i] + 3.0 * p1[i] - 3.0 * p2[i] + p3[i]) * t3)
+    };
+    [axis(0), axis(1)]
+}
+
+/// Walks a polyline and emits points every `spacing` along it, always keeping both ends.
+fn resample(polyline: &[[f64; 2]], spacing: f64) -> Vec<[f64; 2]> {
+    let mut points = vec![polyline[0]];
+    // Distance travelled since the last emitted point.
+    let mut travelled = 0.0;
+    for pair in polyline.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let length = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+        let mut along = spacing - travelled;
+        while along <= length {
+            let t = along / length;
+            points.push([a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]);
+            along += spacing;
+        }
+        travelled = length - (along - spacing);
+    }
+    let last = polyline[polyline.len() - 1];
+    if points[points.len() - 1] != last {
+        points.push(last);
+    }
+    points
+}
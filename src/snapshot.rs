@@ -0,0 +1,171 @@
+use rocket::State;
+use rocket_contrib::JSON;
+use std::collections::HashMap;
+use proto;
+use super::Problem;
+use error::Error;
+use journal::{Author, Event};
+use revision::{IfMatch, Tagged};
+use settings::PlannerSettings;
+use validate;
+
+/// The whole of a problem as a single document, for saving and restoring scenarios.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(default)]
+    pub obstacles: HashMap<String, proto::Obstacle>,
+    #[serde(default)]
+    pub robot: Option<proto::Robot>,
+    #[serde(default)]
+    pub goal: Option<proto::Goal>,
+    #[serde(default)]
+    pub boundary: Option<proto::Boundary>,
+    #[serde(default)]
+    pub planner: PlannerSettings,
+}
+
+impl Snapshot {
+    pub fn of(problem: &Problem) -> Snapshot {
+        Snapshot {
+            obstacles: problem.obstacles.clone(),
+            robot: problem.robot.clone(),
+            goal: problem.goal.clone(),
+            boundary: problem.boundary.clone(),
+            planner: problem.planner.clone(),
+        }
+    }
+
+    /// Builds the problem described by the snapshot, rebuilding its obstacle index.
+    pub fn into_problem(self) -> Problem {
+        let mut problem = Problem {
+            robot: self.robot,
+            goal: self.goal,
+            boundary: self.boundary,
+            planner: self.planner,
+            ..Problem::default()
+        };
+        for (id, obstacle) in self.obstacles {
+            problem.insert_obstacle(id, obstacle);
+        }
+        problem
+    }
+}
+
+#[get("/<problem>/Snapshot")]
+fn get(state: State<super::State>, problem: &str) -> Result<Tagged<JSON<Snapshot>>, Error> {
+    // Attempt to access the problem.
+    state.read(problem, |problem| {
+        Ok(Tagged(JSON(Snapshot::of(problem)), problem.version()))
+    })
+}
+
+#[put("/<problem>/Snapshot", data = "<snapshot>")]
+fn put(state: State<super::State>,
+       if_match: IfMatch,
+       author: Author,
+       problem: &str,
+       snapshot: JSON<Snapshot>) -> Result<Tagged<()>, Error> {
+    // Attempt to access the problem.
+    state.change(problem, &author, |problem| {
+        if_match.check(problem)?;
+        // Check everything before touching the problem so a bad snapshot changes nothing.
+        validate::snapshot(&snapshot)?;
+        let revision = problem.revision + 1;
+        let metadata = problem.metadata.clone();
+        *problem = snapshot.0.clone().into_problem();
+        problem.revision = revision;
+        problem.metadata = metadata;
+        Ok((Tagged((), problem.version()), Event::Snapshot(snapshot.0)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType};
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // Get the snapshot of "test" before "test" exists so it should fail.
+        let mut request = MockRequest::new(Method::Get, "/test/Snapshot");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Add "test" to the problems.
+        let mut request = MockRequest::new(Method::Post, "/test");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Replace the whole problem in one go.
+        let scenario = r#"{
+            "obstacles": {
+                "wall": { "width": 1.0, "length": 5.0, "point": { "x": 4.0, "y": 0.0 } }
+            },
+            "robot": { "point": { "x": 1.0, "y": 1.0 }, "radius": 0.5 },
+            "goal": { "point": { "x": 9.0, "y": 1.0 } },
+            "boundary": { "width": 10.0, "length": 10.0, "point": { "x": 0.0, "y": 0.0 } },
+            "planner": { "resolution": 0.25 }
+        }"#;
+        let mut request = MockRequest::new(Method::Put, "/test/Snapshot")
+            .header(ContentType::JSON)
+            .body(scenario);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // The entities are visible through their own routes and the obstacle index.
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles?within=0,0,10,10");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_deser(&mut response), Some(vec![String::from("wall")]));
+        let mut request = MockRequest::new(Method::Get, "/test/Goal");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Export it again and get back what was imported.
+        let mut request = MockRequest::new(Method::Get, "/test/Snapshot");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let exported = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(exported, serde_json::from_str::<serde_json::Value>(scenario).unwrap());
+
+        // A snapshot with the robot outside the boundary is rejected as a whole.
+        let mut request = MockRequest::new(Method::Put, "/test/Snapshot")
+            .header(ContentType::JSON)
+            .body(r#"{
+                "robot": { "point": { "x": 20.0, "y": 1.0 }, "radius": 0.5 },
+                "boundary": { "width": 10.0, "length": 10.0, "point": { "x": 0.0, "y": 0.0 } }
+            }"#);
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["field"], "robot.point");
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles/wall");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+
+        // An empty snapshot clears the problem.
+        let mut request = MockRequest::new(Method::Put, "/test/Snapshot")
+            .header(ContentType::JSON)
+            .body("{}");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/test/Obstacles");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(body_deser::<Vec<String>>(&mut response), Some(vec![]));
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use proto;
+
+/// Side length of the square buckets the map is divided into until there are obstacles to size
+/// them by.
+const DEFAULT_BUCKET_SIZE: f64 = 1.0;
+
+/// The index isn't resized for fewer obstacles than this.
+const MIN_OBSTACLES_TO_RESIZE: usize = 4;
+
+/// Obstacles spanning more buckets than this are kept in a separate list instead.
+const MAX_BUCKETS_PER_OBSTACLE: i64 = 4096;
+
+/// A uniform grid over the map that finds the obstacles near an area without a full scan.
+#[derive(Clone)]
+pub struct SpatialIndex {
+    /// Side length of the square buckets, the median size of the obstacles when the index was
+    /// last resized.
+    bucket_size: f64,
+    /// How many obstacles there were when the index was last resized.
+    sized_for: usize,
+    buckets: HashMap<(i64, i64), HashSet<String>>,
+    /// Obstacles too large to bucket, which are candidates for every query.
+    oversized: HashSet<String>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> SpatialIndex {
+        SpatialIndex {
+            bucket_size: DEFAULT_BUCKET_SIZE,
+            sized_for: 0,
+            buckets: HashMap::new(),
+            oversized: HashSet::new(),
+        }
+    }
+}
+
+/// An axis-aligned rectangle given by its minimum and maximum corners.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+}
+
+impl Rect {
+    pub fn of_obstacle(obstacle: &proto::Obstacle) -> Rect {
+        Rect {
+            min: [obstacle.point.x, obstacle.point.y],
+            max: [obstacle.point.x + obstacle.width, obstacle.point.y + obstacle.length],
+        }
+    }
+
+    /// Whether `other` lies entirely inside this rectangle.
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.min[0] >= self.min[0] && other.min[1] >= self.min[1] &&
+            other.max[0] <= self.max[0] && other.max[1] <= self.max[1]
+    }
+
+    /// The shortest distance from a point to the rectangle, which is zero inside it.
+    pub fn distance_to(&self, point: [f64; 2]) -> f64 {
+        let dx = (self.min[0] - point[0]).max(0.0).max(point[0] - self.max[0]);
+        let dy = (self.min[1] - point[1]).max(0.0).max(point[1] - self.max[1]);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    /// The range of buckets of side `size` the rectangle overlaps.
+    fn buckets(&self, size: f64) -> ((i64, i64), (i64, i64)) {
+        let bucket = |v: f64| (v / size).floor() as i64;
+        ((bucket(self.min[0]), bucket(self.min[1])), (bucket(self.max[0]), bucket(self.max[1])))
+    }
+}
+
+/// The number of buckets in a range, saturating instead of overflowing.
+fn bucket_count(start: (i64, i64), end: (i64, i64)) -> i64 {
+    let span = |start: i64, end: i64| end.saturating_sub(start).saturating_add(1);
+    span(start.0, end.0).saturating_mul(span(start.1, end.1))
+}
+
+impl SpatialIndex {
+    pub fn insert(&mut self, id: &str, obstacle: &proto::Obstacle) {
+        let (start, end) = Rect::of_obstacle(obstacle).buckets(self.bucket_size);
+        if bucket_count(start, end) > MAX_BUCKETS_PER_OBSTACLE {
+            self.oversized.insert(String::from(id));
+            return;
+        }
+        for x in start.0..end.0 + 1 {
+            for y in start.1..end.1 + 1 {
+                self.buckets.entry((x, y)).or_insert_with(HashSet::new).insert(String::from(id));
+            }
+        }
+    }
+
+    /// Removes an obstacle, which must be the same one it was inserted with.
+    pub fn remove(&mut self, id: &str, obstacle: &proto::Obstacle) {
+        let (start, end) = Rect::of_obstacle(obstacle).buckets(self.bucket_size);
+        if bucket_count(start, end) > MAX_BUCKETS_PER_OBSTACLE {
+            self.oversized.remove(id);
+            return;
+        }
+        for x in start.0..end.0 + 1 {
+            for y in start.1..end.1 + 1 {
+                let empty = match self.buckets.get_mut(&(x, y)) {
+                    Some(bucket) => {
+                        bucket.remove(id);
+                        bucket.is_empty()
+                    }
+                    None => false,
+                };
+                if empty {
+                    self.buckets.remove(&(x, y));
+                }
+            }
+        }
+    }
+
+    /// The IDs of every obstacle that might overlap the area, possibly with false positives.
+    pub fn candidates(&self, area: &Rect) -> HashSet<String> {
+        let mut candidates = self.oversized.clone();
+        let (start, end) = area.buckets(self.bucket_size);
+        if bucket_count(start, end) > self.buckets.len() as i64 {
+            // Visiting the occupied buckets is cheaper than visiting the whole area.
+            for (&(x, y), bucket) in &self.buckets {
+                if x >= start.0 && x <= end.0 && y >= start.1 && y <= end.1 {
+                    candidates.extend(bucket.iter().cloned());
+                }
+            }
+        } else {
+            for x in start.0..end.0 + 1 {
+                for y in start.1..end.1 + 1 {
+                    if let Some(bucket) = self.buckets.get(&(x, y)) {
+                        candidates.extend(bucket.iter().cloned());
+                    }
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Rebuilds the index with buckets the median size of `obstacles`, the obstacles it holds,
+    /// once their number has doubled or halved since the last time. The rebuilds cost about as
+    /// much as the changes in between them.
+    pub fn resize(&mut self, obstacles: &HashMap<String, proto::Obstacle>) {
+        let count = obstacles.len();
+        if count < 2 * cmp::max(self.sized_for, MIN_OBSTACLES_TO_RESIZE) &&
+           2 * count >= self.sized_for {
+            return;
+        }
+        let mut sizes: Vec<f64> = obstacles.values()
+            .map(|obstacle| obstacle.width.max(obstacle.length))
+            .filter(|size| size.is_finite() && *size > 0.0)
+            .collect();
+        sizes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        *self = SpatialIndex {
+            bucket_size: sizes.get(sizes.len() / 2).cloned().unwrap_or(DEFAULT_BUCKET_SIZE),
+            sized_for: count,
+            ..SpatialIndex::default()
+        };
+        for (id, obstacle) in obstacles {
+            self.insert(id, obstacle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proto;
+    use std::collections::HashMap;
+    use super::{Rect, SpatialIndex};
+
+    #[test]
+    fn test() {
+        let small = proto::Obstacle{
+            width: 1.0,
+            length: 1.0,
+            point: proto::Point{ x: 2.5, y: 2.5 },
+        };
+        let huge = proto::Obstacle{
+            width: 1e6,
+            length: 1e6,
+            point: proto::Point{ x: -5e5, y: -5e5 },
+        };
+        let mut index = SpatialIndex::default();
+        index.insert("small", &small);
+        index.insert("huge", &huge);
+
+        // Both show up near the small obstacle.
+        let area = Rect{ min: [2.0, 2.0], max: [3.0, 3.0] };
+        let candidates = index.candidates(&area);
+        assert!(candidates.contains("small") && candidates.contains("huge"));
+
+        // Only the huge one is anywhere near this area.
+        let area = Rect{ min: [100.0, 100.0], max: [101.0, 101.0] };
+        let candidates = index.candidates(&area);
+        assert!(!candidates.contains("small") && candidates.contains("huge"));
+
+        // Removed obstacles are gone for good.
+        index.remove("small", &small);
+        index.remove("huge", &huge);
+        assert!(index.candidates(&Rect{ min: [-1e9, -1e9], max: [1e9, 1e9] }).is_empty());
+
+        // Enough obstacles size the buckets after them.
+        let mut obstacles = HashMap::new();
+        for i in 0..8 {
+            let id = format!("crate{}", i);
+            let obstacle = proto::Obstacle{
+                width: 10.0,
+                length: 5.0,
+                point: proto::Point{ x: 20.0 * i as f64, y: 0.0 },
+            };
+            index.insert(&id, &obstacle);
+            obstacles.insert(id, obstacle);
+            index.resize(&obstacles);
+        }
+        assert_eq!(index.bucket_size, 10.0);
+        let area = Rect{ min: [41.0, 1.0], max: [42.0, 2.0] };
+        assert_eq!(index.candidates(&area).into_iter().collect::<Vec<_>>(),
+                   vec![String::from("crate2")]);
+    }
+}
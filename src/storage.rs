@@ -0,0 +1,287 @@
+use rocket::http::Status;
+use serde_json;
+use std::fs::{self, File};
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use super::Problem;
+use error::Error;
+use journal::{Change, Journal};
+use metadata::Metadata;
+use snapshot::Snapshot;
+
+/// Where problems are kept between restarts of the server.
+///
+/// The registry hands a backend every change along with the whole problem it led to, so a
+/// backend can keep either.
+pub trait Storage: Send + Sync {
+    /// Every stored problem, by name.
+    fn load(&self) -> Result<Vec<(String, Problem)>, Error>;
+
+    /// Saves a problem after a change, replacing any earlier copy.
+    fn save(&self, name: &str, problem: &Problem, change: &Change) -> Result<(), Error>;
+
+    fn remove(&self, name: &str, change: &Change) -> Result<(), Error>;
+
+    /// Moves a problem to a new name, so that it is under one name or the other even if the
+    /// server stops part way through.
+    fn rename(&self, name: &str, to: &str, problem: &Problem, change: &Change)
+              -> Result<(), Error>;
+}
+
+/// Picks a backend by the `storage` name used in `Rocket.toml`.
+pub fn by_name(name: &str, path: &str) -> Option<Box<Storage>> {
+    match name {
+        "memory" => Some(Box::new(Memory)),
+        "json" => Some(Box::new(JsonFiles::new(path))),
+        "journal" => Some(Box::new(Journal::new(path))),
+        _ => None,
+    }
+}
+
+/// Keeps nothing, so problems only last as long as the server.
+pub struct Memory;
+
+impl Storage for Memory {
+    fn load(&self) -> Result<Vec<(String, Problem)>, Error> {
+        Ok(vec![])
+    }
+
+    fn save(&self, _: &str, _: &Problem, _: &Change) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn remove(&self, _: &str, _: &Change) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn rename(&self, _: &str, _: &str, _: &Problem, _: &Change) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A stored problem, which keeps its revision so `ETag`s stay valid across restarts.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub revision: u64,
+    pub problem: Snapshot,
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+impl Record {
+    pub fn of(problem: &Problem) -> Record {
+        Record {
+            revision: problem.revision,
+            problem: Snapshot::of(problem),
+            metadata: problem.metadata.clone(),
+        }
+    }
+
+    /// Keeps a problem that is no longer needed, without copying it.
+    pub fn from_problem(problem: Problem) -> Record {
+        Record {
+            revision: problem.revision,
+            problem: Snapshot {
+                obstacles: problem.obstacles,
+                robot: problem.robot,
+                goal: problem.goal,
+                boundary: problem.boundary,
+                planner: problem.planner,
+            },
+            metadata: problem.metadata,
+        }
+    }
+
+    pub fn into_problem(self) -> Problem {
+        let mut problem = self.problem.into_problem();
+        problem.revision = self.revision;
+        problem.metadata = self.metadata;
+        problem
+    }
+}
+
+/// Keeps each problem as a JSON file in a directory.
+pub struct JsonFiles {
+    directory: PathBuf,
+}
+
+impl JsonFiles {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> JsonFiles {
+        JsonFiles { directory: directory.into() }
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", encode(name)))
+    }
+}
+
+pub fn storage_error<E: Display>(error: E) -> Error {
+    Error::new(Status::InternalServerError,
+               "storage",
+               format!("the problem could not be stored: {}", error))
+}
+
+impl Storage for JsonFiles {
+    fn load(&self) -> Result<Vec<(String, Problem)>, Error> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            // Nothing has been saved yet.
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(storage_error(e)),
+        };
+        let mut problems = vec![];
+        for entry in entries {
+            let path = entry.map_err(storage_error)?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(file) if file.ends_with(".json") => decode(&file[..file.len() - 5]),
+                _ => None,
+            };
+            // Skip anything that isn't a problem, such as a half-written temporary file.
+            let name = match name {
+                Some(name) => name,
+                None => continue,
+            };
+            let file = File::open(&path).map_err(storage_error)?;
+            let record: Record = serde_json::from_reader(file).map_err(storage_error)?;
+            problems.push((name, record.into_problem()));
+        }
+        Ok(problems)
+    }
+
+    fn save(&self, name: &str, problem: &Problem, _: &Change) -> Result<(), Error> {
+        let json = serde_json::to_vec(&Record::of(problem)).map_err(storage_error)?;
+        fs::create_dir_all(&self.directory).map_err(storage_error)?;
+
+        // Write a temporary file and move it over the old one so a crash can't leave half a
+        // problem behind.
+        let file = self.file(name);
+        let temporary = file.with_extension("json.tmp");
+        File::create(&temporary)
+            .and_then(|mut out| out.write_all(&json).and_then(|_| out.sync_all()))
+            .and_then(|_| fs::rename(&temporary, &file))
+            .map_err(storage_error)
+    }
+
+    fn remove(&self, name: &str, _: &Change) -> Result<(), Error> {
+        match fs::remove_file(self.file(name)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.map_err(storage_error),
+        }
+    }
+
+    fn rename(&self, name: &str, to: &str, problem: &Problem, change: &Change)
+              -> Result<(), Error> {
+        match fs::rename(self.file(name), self.file(to)) {
+            // Nothing was saved under the old name, so there is nothing to move.
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => self.save(to, problem, change),
+            result => result.map_err(storage_error),
+        }
+    }
+}
+
+/// Turns a problem name into a file name, escaping anything but letters, digits, `-` and `_`
+/// as `%XX` so names like `..` can't escape the directory.
+pub fn encode(name: &str) -> String {
+    let mut file = String::new();
+    for &byte in name.as_bytes() {
+        match byte {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' => file.push(byte as char),
+            _ => file.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    file
+}
+
+/// Turns a file name back into a problem name, or `None` if `encode` wouldn't have made it, so
+/// two files that differ only in how a name is escaped can't both load as the same problem.
+pub fn decode(file: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = file.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = match tail.get(..2).and_then(|hex| ::std::str::from_utf8(hex).ok()) {
+                Some(hex) => hex,
+                None => return None,
+            };
+            match u8::from_str_radix(hex, 16) {
+                Ok(byte) => bytes.push(byte),
+                Err(_) => return None,
+            }
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    match String::from_utf8(bytes) {
+        Ok(ref name) if encode(name) != file => None,
+        name => name.ok(),
+    }
+}
+
+/// A new directory for a test to keep problems in, so tests never see each other's files.
+#[cfg(test)]
+pub fn test_directory(name: &str) -> PathBuf {
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    env::temp_dir().join(format!("a4-{}-{}-{}", name, now.as_secs(), now.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod test {
+    use proto;
+    use std::fs;
+    use super::{test_directory, JsonFiles, Storage};
+    use journal::{Author, Change, Event};
+    use registry::Registry;
+    use snapshot::Snapshot;
+
+    #[test]
+    fn test() {
+        let directory = test_directory("storage-test");
+
+        // Changes to a registry are written through to its storage.
+        let registry = Registry::new(Box::new(JsonFiles::new(&directory))).unwrap();
+        let author = Author::default();
+        registry.insert("test", Default::default(), &author).unwrap();
+        registry.insert("../odd name", Default::default(), &author).unwrap();
+        registry.change("test", &author, |problem| {
+            problem.insert_obstacle(String::from("a"), proto::Obstacle{
+                width: 1.0,
+                length: 2.0,
+                point: proto::Point{ x: 3.0, y: 4.0 },
+            });
+            problem.revision += 1;
+            Ok(((), Event::Snapshot(Snapshot::of(problem))))
+        }).unwrap();
+        registry.remove_if("../odd name", &author, |_| Ok(())).unwrap();
+
+        // A new registry on the same directory picks up where the old one left off.
+        let registry = Registry::new(Box::new(JsonFiles::new(&directory))).unwrap();
+        assert_eq!(registry.list().into_iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                   vec![String::from("test")]);
+        let problem = registry.snapshot("test").unwrap();
+        assert_eq!(problem.revision, 1);
+        assert_eq!(problem.obstacles["a"].point.y, 4.0);
+
+        // Names that aren't safe as file names survive the round trip.
+        let storage = JsonFiles::new(&directory);
+        let change = Change { author: &author, event: Event::Created(Snapshot::of(&problem)) };
+        storage.save("../odd name", &problem, &change).unwrap();
+        assert!(directory.join("%2E%2E%2Fodd%20name.json").exists());
+        // Files escaping the same name in other ways, such as in lowercase, are left alone.
+        for copy in &["%2e%2e%2fodd%20name.json", "%74est.json"] {
+            fs::copy(directory.join("test.json"), directory.join(copy)).unwrap();
+        }
+        let mut names: Vec<String> = storage.load().unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![String::from("../odd name"), String::from("test")]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::JSON;
+use error::Error;
+use journal::{Author, Event};
+use registry::Registry;
+use revision::{IfMatch, Tagged};
+use snapshot::Snapshot;
+use validate;
+
+/// The name new problems can't take, since the template routes would hide theirs.
+pub const RESERVED: &'static str = "Templates";
+
+/// Problems that new problems can be created from, such as a base warehouse layout.
+///
+/// Templates are kept in a registry of their own, so their names never clash with those of
+/// problems, and are stored, journalled and versioned the same way problems are.
+pub struct Templates {
+    registry: Registry,
+}
+
+impl Templates {
+    pub fn new(registry: Registry) -> Templates {
+        Templates { registry: registry }
+    }
+
+    /// The contents of a template, to create a problem from.
+    pub fn get(&self, name: &str) -> Result<Snapshot, Error> {
+        self.registry.read(name, |template| Ok(Snapshot::of(template)))
+            .map_err(|_| not_found(name))
+    }
+}
+
+fn not_found(name: &str) -> Error {
+    Error::not_found("template", format!("template \"{}\" does not exist", name))
+}
+
+#[get("/Templates", rank = -1)]
+fn get_all(templates: State<Templates>) -> JSON<Vec<String>> {
+    let mut names: Vec<String> = templates.registry.list()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    JSON(names)
+}
+
+#[get("/Templates/<name>", rank = -1)]
+fn get(templates: State<Templates>, name: &str) -> Result<Tagged<JSON<Snapshot>>, Error> {
+    templates.registry.read(name, |template| {
+        Ok(Tagged(JSON(Snapshot::of(template)), template.version()))
+    }).map_err(|_| not_found(name))
+}
+
+/// Adds or replaces a template.
+#[put("/Templates/<name>", data = "<snapshot>", rank = -1)]
+fn put(templates: State<Templates>,
+       if_match: IfMatch,
+       author: Author,
+       name: &str,
+       snapshot: JSON<Snapshot>) -> Result<Tagged<()>, Error> {
+    validate::snapshot(&snapshot)?;
+    let template = snapshot.0.clone().into_problem();
+    let event = Event::Snapshot(snapshot.0);
+    let version = templates.registry.put(name, template, event, &author, |existing| {
+        match existing {
+            Some(existing) => if_match.check(existing),
+            None => if_match.check_missing(),
+        }
+    })?;
+    Ok(Tagged((), version))
+}
+
+#[delete("/Templates/<name>", rank = -1)]
+fn delete(templates: State<Templates>, if_match: IfMatch, author: Author, name: &str)
+          -> Result<(), Error> {
+    templates.registry.remove_if(name, &author, |template| if_match.check(template))
+        .map_err(|error| if error.status == Status::NotFound { not_found(name) } else { error })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde;
+    extern crate serde_json;
+    use rocket::testing::MockRequest;
+    use rocket::Response;
+    use rocket::http::{Status, Method, ContentType, Header};
+
+    fn body_deser<T: serde::Deserialize>(response: &mut Response) -> Option<T> {
+        response.body()
+            .and_then(|b| b.into_string())
+            .map(|s| {
+                serde_json::from_str(&s)
+                    .unwrap_or_else(|e| panic!("Failed to parse body as JSON: {:?}", e))
+            })
+    }
+
+    #[test]
+    fn test() {
+        // Make the mock server.
+        let rocket = super::super::new_mounted_rocket();
+
+        // There are no templates to start with.
+        let mut request = MockRequest::new(Method::Get, "/Templates");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_deser::<Vec<String>>(&mut response), Some(vec![]));
+
+        // Creating a problem from a template that doesn't exist fails.
+        let mut request = MockRequest::new(Method::Post, "/variant?template=warehouse");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["field"], "template");
+
+        // A misspelled parameter doesn't quietly create an empty problem.
+        let mut request = MockRequest::new(Method::Post, "/variant?tmplate=warehouse");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["field"], "tmplate");
+        let mut request = MockRequest::new(Method::Get, "/variant/Metadata");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Add a warehouse layout as a template, even one named after a problem route.
+        let layout = r#"{
+            "obstacles": {
+                "shelf": { "width": 1.0, "length": 5.0, "point": { "x": 4.0, "y": 0.0 } }
+            },
+            "boundary": { "width": 10.0, "length": 10.0, "point": { "x": 0.0, "y": 0.0 } }
+        }"#;
+        for name in &["warehouse", "Robot"] {
+            let mut request = MockRequest::new(Method::Put, format!("/Templates/{}", name))
+                .header(ContentType::JSON)
+                .body(layout);
+            let response = request.dispatch_with(&rocket);
+            assert_eq!(response.status(), Status::Ok);
+        }
+        let mut request = MockRequest::new(Method::Get, "/Templates/warehouse");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let first = String::from(response.headers().get_one("ETag").unwrap());
+        assert!(first.ends_with("-0\""));
+        let template = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(template, serde_json::from_str::<serde_json::Value>(layout).unwrap());
+
+        // Templates aren't listed as problems.
+        let mut request = MockRequest::new(Method::Get, "/");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(body_deser::<Vec<String>>(&mut response), Some(vec![]));
+
+        // Templates are versioned like problems, so a stale change is refused.
+        let mut request = MockRequest::new(Method::Put, "/Templates/warehouse")
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", first.clone()))
+            .body(layout);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Put, "/Templates/warehouse")
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", first.clone()))
+            .body(layout);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let mut request = MockRequest::new(Method::Delete, "/Templates/warehouse")
+            .header(Header::new("If-Match", first));
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::PreconditionFailed);
+
+        // An invalid template is rejected.
+        let mut request = MockRequest::new(Method::Put, "/Templates/broken")
+            .header(ContentType::JSON)
+            .body(r#"{ "boundary": { "width": -1.0, "length": 1.0,
+                                     "point": { "x": 0.0, "y": 0.0 } } }"#);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        // A template can't match a revision before it exists.
+        let mut request = MockRequest::new(Method::Put, "/Templates/missing")
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", "*"))
+            .body(layout);
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let mut request = MockRequest::new(Method::Get, "/Templates/missing");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Problems made from the template start out as a copy of it.
+        let mut request = MockRequest::new(Method::Post, "/variant?template=warehouse");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/variant/Snapshot");
+        let mut response = request.dispatch_with(&rocket);
+        assert!(response.headers().get_one("ETag").unwrap().ends_with("-0\""));
+        let body = body_deser::<serde_json::Value>(&mut response).unwrap();
+        assert_eq!(body["obstacles"], template["obstacles"]);
+
+        // The problem can't be called "Templates".
+        let mut request = MockRequest::new(Method::Post, "/Templates");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Deleting the template leaves problems made from it alone.
+        let mut request = MockRequest::new(Method::Delete, "/Templates/warehouse");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        let mut request = MockRequest::new(Method::Get, "/Templates");
+        let mut response = request.dispatch_with(&rocket);
+        assert_eq!(body_deser::<Vec<String>>(&mut response), Some(vec![String::from("Robot")]));
+        let mut request = MockRequest::new(Method::Get, "/variant/Obstacles/shelf");
+        let response = request.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
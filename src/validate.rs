@@ -0,0 +1,240 @@
+use rocket::http::Status;
+use proto;
+use super::Problem;
+use error::Error;
+use snapshot::Snapshot;
+
+/// A single problem found while validating a payload.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Violation {
+    /// The offending field of the payload, such as `point.x`.
+    pub field: String,
+    pub message: String,
+}
+
+/// Invariants a payload must satisfy on its own, regardless of the problem it is added to.
+pub trait Validate {
+    /// Records every violated invariant, naming fields relative to `prefix`.
+    fn validate(&self, prefix: &str, violations: &mut Vec<Violation>);
+}
+
+fn violation(violations: &mut Vec<Violation>, prefix: &str, field: &str, message: &str) {
+    violations.push(Violation {
+        field: format!("{}{}", prefix, field),
+        message: String::from(message),
+    });
+}
+
+fn finite(violations: &mut Vec<Violation>, prefix: &str, field: &str, value: f64) {
+    if !value.is_finite() {
+        violation(violations, prefix, field, "must be a finite number");
+    }
+}
+
+fn positive(violations: &mut Vec<Violation>, prefix: &str, field: &str, value: f64) {
+    if !(value > 0.0 && value.is_finite()) {
+        violation(violations, prefix, field, "must be a positive finite number");
+    }
+}
+
+fn non_negative(violations: &mut Vec<Violation>, prefix: &str, field: &str, value: f64) {
+    if !(value >= 0.0 && value.is_finite()) {
+        violation(violations, prefix, field, "must be a non-negative finite number");
+    }
+}
+
+impl Validate for proto::Point {
+    fn validate(&self, prefix: &str, violations: &mut Vec<Violation>) {
+        finite(violations, prefix, "x", self.x);
+        finite(violations, prefix, "y", self.y);
+    }
+}
+
+impl Validate for proto::Robot {
+    fn validate(&self, prefix: &str, violations: &mut Vec<Violation>) {
+        self.point.validate(&format!("{}point.", prefix), violations);
+        positive(violations, prefix, "radius", self.radius);
+    }
+}
+
+impl Validate for proto::Goal {
+    fn validate(&self, prefix: &str, violations: &mut Vec<Violation>) {
+        self.point.validate(&format!("{}point.", prefix), violations);
+    }
+}
+
+impl Validate for proto::Boundary {
+    fn validate(&self, prefix: &str, violations: &mut Vec<Violation>) {
+        self.point.validate(&format!("{}point.", prefix), violations);
+        positive(violations, prefix, "width", self.width);
+        positive(violations, prefix, "length", self.length);
+    }
+}
+
+impl Validate for proto::Obstacle {
+    fn validate(&self, prefix: &str, violations: &mut Vec<Violation>) {
+        self.point.validate(&format!("{}point.", prefix), violations);
+        non_negative(violations, prefix, "width", self.width);
+        non_negative(violations, prefix, "length", self.length);
+    }
+}
+
+/// Whether a point lies inside or on the edge of a boundary.
+fn inside(boundary: &proto::Boundary, point: &proto::Point) -> bool {
+    point.x >= boundary.point.x && point.x <= boundary.point.x + boundary.width &&
+        point.y >= boundary.point.y && point.y <= boundary.point.y + boundary.length
+}
+
+/// Turns the violations into a 422 error, if there are any.
+fn check(violations: Vec<Violation>) -> Result<(), Error> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let message = if violations.len() == 1 {
+        format!("{} {}", violations[0].field, violations[0].message)
+    } else {
+        format!("the payload has {} invalid fields", violations.len())
+    };
+    let field = violations[0].field.clone();
+    Err(Error::new(Status::UnprocessableEntity, "invalid_payload", message)
+        .with_field(field)
+        .with_details(violations))
+}
+
+/// Checks a robot before it is placed in the problem.
+pub fn robot(problem: &Problem, robot: &proto::Robot) -> Result<(), Error> {
+    let mut violations = Vec::new();
+    robot.validate("", &mut violations);
+    if let Some(ref boundary) = problem.boundary {
+        if !inside(boundary, &robot.point) {
+            violation(&mut violations, "", "point", "must be inside the boundary");
+        }
+    }
+    check(violations)
+}
+
+/// Checks a goal before it is placed in the problem.
+pub fn goal(problem: &Problem, goal: &proto::Goal) -> Result<(), Error> {
+    let mut violations = Vec::new();
+    goal.validate("", &mut violations);
+    if let Some(ref boundary) = problem.boundary {
+        if !inside(boundary, &goal.point) {
+            violation(&mut violations, "", "point", "must be inside the boundary");
+        }
+    }
+    check(violations)
+}
+
+/// Checks a boundary before it is placed in the problem.
+///
+/// The robot and goal that are already set must stay inside it.
+pub fn boundary(problem: &Problem, boundary: &proto::Boundary) -> Result<(), Error> {
+    let mut violations = Vec::new();
+    boundary.validate("", &mut violations);
+    if violations.is_empty() {
+        if let Some(ref robot) = problem.robot {
+            if !inside(boundary, &robot.point) {
+                violation(&mut violations, "", "point", "must leave the robot inside the boundary");
+            }
+        }
+        if let Some(ref goal) = problem.goal {
+            if !inside(boundary, &goal.point) {
+                violation(&mut violations, "", "point", "must leave the goal inside the boundary");
+            }
+        }
+    }
+    check(violations)
+}
+
+/// Checks an obstacle before it is placed in the problem.
+pub fn obstacle(obstacle: &proto::Obstacle) -> Result<(), Error> {
+    let mut violations = Vec::new();
+    obstacle.validate("", &mut violations);
+    check(violations)
+}
+
+/// Checks a whole problem before it replaces an existing one.
+pub fn snapshot(snapshot: &Snapshot) -> Result<(), Error> {
+    let mut violations = Vec::new();
+    if let Some(ref robot) = snapshot.robot {
+        robot.validate("robot.", &mut violations);
+    }
+    if let Some(ref goal) = snapshot.goal {
+        goal.validate("goal.", &mut violations);
+    }
+    if let Some(ref boundary) = snapshot.boundary {
+        boundary.validate("boundary.", &mut violations);
+        if let Some(ref robot) = snapshot.robot {
+            if !inside(boundary, &robot.point) {
+                violation(&mut violations, "robot.", "point", "must be inside the boundary");
+            }
+        }
+        if let Some(ref goal) = snapshot.goal {
+            if !inside(boundary, &goal.point) {
+                violation(&mut violations, "goal.", "point", "must be inside the boundary");
+            }
+        }
+    }
+    // Sort the obstacles so the violations come out in a stable order.
+    let mut ids: Vec<&String> = snapshot.obstacles.keys().collect();
+    ids.sort();
+    for id in ids {
+        snapshot.obstacles[id].validate(&format!("obstacles.{}.", id), &mut violations);
+    }
+    if !snapshot.planner.is_valid() {
+        violation(&mut violations, "planner.", "resolution", "must be a positive finite number");
+    }
+    check(violations)
+}
+
+#[cfg(test)]
+mod test {
+    use proto;
+    use super::Validate;
+
+    #[test]
+    fn test() {
+        let problem = super::super::Problem::default();
+
+        // A robot with no size and a coordinate that isn't a number has two problems.
+        let robot = proto::Robot{
+            point: proto::Point{ x: ::std::f64::NAN, y: 0.0 },
+            radius: 0.0,
+        };
+        let mut violations = Vec::new();
+        robot.validate("", &mut violations);
+        let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(fields, vec!["point.x", "radius"]);
+        assert!(super::robot(&problem, &robot).is_err());
+
+        // A boundary with negative size is rejected.
+        let boundary = proto::Boundary{
+            width: -1.0,
+            length: 10.0,
+            point: proto::Point{ x: 0.0, y: 0.0 },
+        };
+        assert!(super::boundary(&problem, &boundary).is_err());
+
+        // A boundary that leaves the goal outside is rejected.
+        let mut problem = super::super::Problem::default();
+        problem.goal = Some(proto::Goal{
+            point: proto::Point{ x: 20.0, y: 0.0 },
+        });
+        let boundary = proto::Boundary{
+            width: 10.0,
+            length: 10.0,
+            point: proto::Point{ x: 0.0, y: 0.0 },
+        };
+        assert!(super::boundary(&problem, &boundary).is_err());
+
+        // Zero-sized obstacles are fine, negative ones aren't.
+        let mut obstacle = proto::Obstacle{
+            width: 0.0,
+            length: 0.0,
+            point: proto::Point{ x: 0.0, y: 0.0 },
+        };
+        assert!(super::obstacle(&obstacle).is_ok());
+        obstacle.length = -2.0;
+        assert!(super::obstacle(&obstacle).is_err());
+    }
+}